@@ -106,12 +106,14 @@ storage() {
             -o setuid=off \
             -o mountpoint=/mnt/zocker \
             tank/zocker
-        zfs allow -g buildkite-builder \
-            "atime,clone,create,compression,destroy,exec,mount,mountpoint,promote,quota,refquota,rename,setuid,snapshot" \
-            tank/zocker
         set +x
     }
 
+    # zockervols keeps its bookkeeping in `zockervols:` user properties
+    zfs allow -g buildkite-builder \
+        "atime,clone,create,compression,destroy,exec,mount,mountpoint,promote,quota,refquota,rename,setuid,snapshot,userprop" \
+        tank/zocker
+
     [[ "$(zfs_exists zfs tank/builds)" == 1 ]] || {
         set -x
         zfs create \
@@ -160,8 +162,9 @@ config() {
 
     chown -R root:root /etc/systemd/system
     find /etc/systemd/system -type f -exec chmod 644 {} \;
-    chgrp buildkite-agent /etc/systemd/system/docker-volume-prune.sh
-    chmod 754 /etc/systemd/system/docker-volume-prune.sh
+
+    chown -R root:root /etc/zockervols
+    chmod 644 /etc/zockervols/config.json
 
     chown -R root:root /etc/sudoers.d
     chmod 440 /etc/sudoers.d/*
@@ -211,7 +214,7 @@ services() {
     local units=(
         docker
         zockervols.socket
-        docker-volume-prune.timer
+        zockervols-admin.socket
         docker-system-prune.timer
     )
 
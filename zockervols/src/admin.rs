@@ -0,0 +1,1109 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::api::ErrorResponse;
+use crate::backend::{Destroy, Kind, Properties, ZfsBackend};
+use crate::reconcile::Finding;
+use crate::transaction::Transaction;
+use crate::zfs::{
+    basename, is_volume, parse_refquota, sanitize_vol, Cmd, Error, Zfs, PROP_AUTO_SNAPSHOT,
+    PROP_EPHEMERAL, PROP_EXCLUSIVE, PROP_PER_MOUNT_CLONE, PROP_PROFILE, PROP_REASON,
+    PROP_SNAPSHOT_ON_UNMOUNT, PROP_SOURCE_SNAPSHOT, PROP_SOURCE_VOLUME, PROP_TTL, PROP_TUNABLES,
+    TUNABLES,
+};
+
+pub const SNAPSHOT: &str = "Admin.Snapshot";
+pub const ROLLBACK: &str = "Admin.Rollback";
+pub const SNAPSHOTS: &str = "Admin.Snapshots";
+pub const DESTROY_SNAPSHOT: &str = "Admin.DestroySnapshot";
+pub const SET_QUOTA: &str = "Admin.SetQuota";
+pub const CLONES: &str = "Admin.Clones";
+pub const FORCE_UNMOUNT: &str = "Admin.ForceUnmount";
+pub const RECONCILE: &str = "Admin.Reconcile";
+pub const MOUNTS: &str = "Admin.Mounts";
+pub const COMMIT: &str = "Admin.Commit";
+pub const RESTORE: &str = "Admin.Restore";
+pub const LINEAGE: &str = "Admin.Lineage";
+
+/// Operations on volumes which the Docker volume plugin protocol has no
+/// notion of. Served on a separate socket, only accessible to administrators.
+pub trait AdminApi {
+    fn snapshot(&self, rq: SnapshotRequest) -> Result<SnapshotResponse, ErrorResponse>;
+    fn rollback(&self, rq: RollbackRequest) -> Result<(), ErrorResponse>;
+    fn snapshots(&self, rq: SnapshotsRequest) -> Result<SnapshotsResponse, ErrorResponse>;
+    fn destroy_snapshot(&self, rq: DestroySnapshotRequest) -> Result<(), ErrorResponse>;
+    fn set_quota(&self, rq: SetQuotaRequest) -> Result<(), ErrorResponse>;
+    fn clones(&self, rq: ClonesRequest) -> Result<ClonesResponse, ErrorResponse>;
+    fn force_unmount(&self, rq: ForceUnmountRequest)
+        -> Result<ForceUnmountResponse, ErrorResponse>;
+    fn reconcile(&self, rq: ReconcileRequest) -> Result<ReconcileResponse, ErrorResponse>;
+    fn mounts(&self) -> Result<MountsResponse, ErrorResponse>;
+    fn commit(&self, rq: CommitRequest) -> Result<CommitResponse, ErrorResponse>;
+    fn restore(&self, rq: RestoreRequest) -> Result<RestoreResponse, ErrorResponse>;
+    fn lineage(&self, rq: LineageRequest) -> Result<LineageResponse, ErrorResponse>;
+}
+
+// SnapshotRequest takes a snapshot of a volume. If no name is given, the
+// snapshot is named after the current time.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnapshotRequest {
+    pub name: String,
+    pub snapshot: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnapshotResponse {
+    pub snapshot: String,
+}
+
+// SnapshotsRequest lists the snapshots of a volume, oldest first
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnapshotsRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnapshotsResponse {
+    pub snapshots: Vec<SnapshotInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub used: u64,
+    pub referenced: u64,
+    // Volumes cloned from the snapshot
+    pub clones: Vec<String>,
+}
+
+// DestroySnapshotRequest destroys a snapshot of a volume, unless volumes
+// have been cloned from it.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct DestroySnapshotRequest {
+    pub name: String,
+    pub snapshot: String,
+}
+
+// RollbackRequest rolls an unused volume back to one of its snapshots. Unless
+// `destroy_newer` is set, the snapshot must be the most recent one.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct RollbackRequest {
+    pub name: String,
+    pub snapshot: String,
+    #[serde(default)]
+    pub destroy_newer: bool,
+}
+
+// SetQuotaRequest changes the refquota of a volume, given in the same format
+// as the `refquota` volume option.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SetQuotaRequest {
+    pub name: String,
+    pub refquota: String,
+}
+
+// ClonesRequest lists the volumes cloned from snapshots of a volume
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ClonesRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ClonesResponse {
+    pub clones: Vec<CloneInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CloneInfo {
+    pub name: String,
+    pub snapshot: String,
+}
+
+// ForceUnmountRequest unmounts a volume regardless of who is using it
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ForceUnmountRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ForceUnmountResponse {
+    pub owners: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReconcileRequest {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReconcileResponse {
+    pub findings: Vec<Finding>,
+}
+
+// MountsResponse lists the owners of all volumes in use, by volume name
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct MountsResponse {
+    pub mounts: BTreeMap<String, Vec<String>>,
+}
+
+// CommitRequest makes the contents of volume `name`, a clone of another
+// volume, the new contents of that other volume. `name` goes away in the
+// process. The previous contents of the origin are kept as a generation of it,
+// of which the `keep` most recent ones (1 by default) are retained.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct CommitRequest {
+    pub name: String,
+    #[serde(default)]
+    pub keep: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct CommitResponse {
+    pub origin: String,
+    pub generation: String,
+}
+
+// RestoreRequest makes a previous generation of volume `name`, the most recent
+// one if none is given, its contents again. Its current contents are kept as
+// another generation, which is not pruned.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestoreRequest {
+    pub name: String,
+    #[serde(default)]
+    pub generation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestoreResponse {
+    pub restored: String,
+    pub generation: String,
+}
+
+// LineageRequest asks which volumes volume `name` was cloned from, and which
+// were cloned from it, as recorded when they were created.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LineageRequest {
+    pub name: String,
+}
+
+// Ancestors are ordered nearest first, and end with the first one which no
+// longer exists. Descendants are ordered by generation, then name.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LineageResponse {
+    pub ancestors: Vec<LineageInfo>,
+    pub descendants: Vec<LineageInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct LineageInfo {
+    pub name: String,
+    pub source_volume: Option<String>,
+    pub source_snapshot: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Properties making up the options and lineage of a volume, which a
+/// committed clone takes over from its origin.
+const COMMITTED_PROPS: &[&str] = &[
+    "refquota",
+    "compression",
+    "atime",
+    "exec",
+    "setuid",
+    PROP_TTL,
+    PROP_PER_MOUNT_CLONE,
+    PROP_SOURCE_VOLUME,
+    PROP_SOURCE_SNAPSHOT,
+    PROP_REASON,
+    PROP_AUTO_SNAPSHOT,
+    PROP_SNAPSHOT_ON_UNMOUNT,
+    PROP_EPHEMERAL,
+    PROP_EXCLUSIVE,
+    PROP_TUNABLES,
+    PROP_PROFILE,
+];
+
+/// Those of `COMMITTED_PROPS` and `TUNABLES` which can't be inherited, and
+/// what they are reset to instead.
+const NOT_INHERITABLE: &[(&str, &str)] = &[
+    ("refquota", "none"),
+    ("quota", "none"),
+    ("reservation", "none"),
+];
+
+impl Zfs {
+    fn do_snapshot(&self, name: &str, snapshot: Option<&str>) -> Result<String, Error> {
+        let _lock = self.lock(&[name])?;
+        let snapshot = snapshot
+            .map(sanitize_vol)
+            .unwrap_or_else(|| Utc::now().format("%Y%m%d%H%M%S%6f").to_string());
+        self.backend
+            .snapshot(&format!("{}@{}", self.dataset(name), snapshot))?;
+        Ok(snapshot)
+    }
+
+    fn do_rollback(&self, name: &str, snapshot: &str, destroy_newer: bool) -> Result<(), Error> {
+        let _lock = self.lock(&[name])?;
+        let owners = self.owners_of(&sanitize_vol(name))?;
+        if !owners.is_empty() {
+            return Err(Error::VolInUseError(name.to_string(), owners));
+        }
+
+        let snap = self.snapshot_of(name, snapshot)?;
+        self.backend.rollback(&snap, destroy_newer)
+    }
+
+    fn do_snapshots(&self, name: &str) -> Result<Vec<SnapshotInfo>, Error> {
+        let dataset = self.dataset(name);
+        let prefix = format!("{}@", dataset);
+        Ok(self
+            .backend
+            .list(
+                &dataset,
+                Kind::Snapshot,
+                Some(1),
+                &["name", "creation", "used", "referenced", "clones"],
+            )?
+            .into_iter()
+            .filter(|props| props["name"].starts_with(&prefix))
+            .map(|props| {
+                let num = |prop: &str| props[prop].parse().unwrap_or(0);
+                SnapshotInfo {
+                    name: props["name"][prefix.len()..].to_string(),
+                    created_at: Utc.timestamp(num("creation") as i64, 0),
+                    used: num("used"),
+                    referenced: num("referenced"),
+                    clones: clone_names(&props["clones"]),
+                }
+            })
+            .collect())
+    }
+
+    fn do_destroy_snapshot(&self, name: &str, snapshot: &str) -> Result<(), Error> {
+        let _lock = self.lock(&[name])?;
+        let snap = self.snapshot_of(name, snapshot)?;
+        let clones = clone_names(&self.backend.get(&snap, &["clones"])?["clones"]);
+        if !clones.is_empty() {
+            return Err(Error::HasClonesError(basename(&snap), clones));
+        }
+        self.backend.destroy(&snap, Destroy::Single)
+    }
+
+    // The dataset name of snapshot `snapshot` of volume `name`, if it exists
+    fn snapshot_of(&self, name: &str, snapshot: &str) -> Result<String, Error> {
+        let snap = format!("{}@{}", self.dataset(name), sanitize_vol(snapshot));
+        if self.dataset_exists(&snap)? {
+            Ok(snap)
+        } else {
+            Err(Error::NoSuchSnapshotError(name.to_string(), snapshot.to_string()))
+        }
+    }
+
+    fn do_set_quota(&self, name: &str, refquota: &str) -> Result<(), Error> {
+        let _lock = self.lock(&[name])?;
+        let refquota = parse_refquota(refquota)?;
+        self.backend
+            .set(&self.dataset(name), "refquota", &refquota.to_string())
+    }
+
+    fn do_clones(&self, name: &str) -> Result<Vec<CloneInfo>, Error> {
+        let prefix = format!("{}@", self.dataset(name));
+        Ok(self
+            .backend
+            .list(
+                &self.root.to_string_lossy(),
+                Kind::Filesystem,
+                Some(1),
+                &["name", "origin"],
+            )?
+            .into_iter()
+            .filter(|props| props["origin"].starts_with(&prefix))
+            .map(|props| CloneInfo {
+                name: basename(&props["name"]),
+                snapshot: props["origin"][prefix.len()..].to_string(),
+            })
+            .collect())
+    }
+
+    fn do_force_unmount(&self, name: &str) -> Result<Vec<String>, Error> {
+        let _lock = self.lock(&[name])?;
+        let vol = sanitize_vol(name);
+        let owners = self.owners_of(&vol)?;
+
+        if self.per_mount_clone(name)? {
+            for owner in &owners {
+                Cmd::unmount_clone(name, owner).run(&self.root, &*self.backend)?;
+            }
+            self.record_unmount(name);
+        } else if self.get_mountpoint(name).is_ok() {
+            Cmd::unmount(name).run(&self.root, &*self.backend)?;
+            self.record_unmount(name);
+        }
+        // Under whichever names Docker knows the volume by
+        for known in self.mounts.all()?.keys() {
+            if sanitize_vol(known) == vol {
+                self.mounts.forget(known)?;
+            }
+        }
+
+        Ok(owners)
+    }
+
+    fn do_commit(&self, name: &str, keep: usize) -> Result<(String, String), Error> {
+        let (origin, _lock) = loop {
+            let origin = self.origin_of(name)?;
+            let lock = self.lock(&[name, &origin])?;
+            // Another commit may have swapped the origin out from under us
+            if self.origin_of(name)? == origin {
+                break (origin, lock);
+            }
+        };
+        for vol in &[sanitize_vol(name), origin.clone()] {
+            let owners = self.owners_of(vol)?;
+            if !owners.is_empty() {
+                return Err(Error::VolInUseError(vol.to_string(), owners));
+            }
+        }
+
+        let clone = self.dataset(name);
+        let target = self.dataset(&origin);
+        let now = Utc::now().format("%Y%m%d%H%M%S%6f");
+        let snap = format!("{}@commit-{}", clone, now);
+        let generation = format!("gen-{}", now);
+        let retired = format!("{}/{}", clone, generation);
+        let zfs = &*self.backend;
+        let props = COMMITTED_PROPS
+            .iter()
+            .chain(TUNABLES)
+            .cloned()
+            .collect::<Vec<_>>();
+        let previous = zfs.get_local(&clone, &props)?;
+
+        let mut tx = Transaction::new(name);
+        // Inherited properties stay inherited
+        tx.step(
+            "options",
+            || set_local(zfs, &clone, &props, &zfs.get_local(&target, &props)?),
+            || set_local(zfs, &clone, &props, &previous),
+        )?;
+        tx.step(
+            "snapshot",
+            || zfs.snapshot(&snap),
+            || zfs.destroy(&snap, Destroy::Single),
+        )?;
+        tx.step("promote", || zfs.promote(&clone), || zfs.promote(&target))?;
+        // Earlier generations stay with the volume
+        for gen in self.generations(&target)? {
+            let from = format!("{}/{}", target, gen);
+            let to = format!("{}/{}", clone, gen);
+            let (undo_from, undo_to) = (to.clone(), from.clone());
+            tx.step(
+                "move generation",
+                || zfs.rename(&from, &to),
+                move || zfs.rename(&undo_from, &undo_to),
+            )?;
+        }
+        tx.step(
+            "retire",
+            || zfs.rename(&target, &retired),
+            || zfs.rename(&retired, &target),
+        )?;
+        tx.step(
+            "rename",
+            || zfs.rename(&clone, &target),
+            || zfs.rename(&target, &clone),
+        )?;
+        tx.commit();
+
+        // Old generations may still have clones, in which case they stay
+        for old in self.generations(&target)?.iter().rev().skip(keep) {
+            zfs.destroy(&format!("{}/{}", target, old), Destroy::Recursive)
+                .unwrap_or_else(|e| warn!("Failed to remove {} of {}: {:?}", old, origin, e));
+        }
+
+        Ok((origin, generation))
+    }
+
+    fn do_restore(&self, name: &str, generation: Option<&str>) -> Result<(String, String), Error> {
+        let _lock = self.lock(&[name])?;
+        let vol = sanitize_vol(name);
+        let owners = self.owners_of(&vol)?;
+        if !owners.is_empty() {
+            return Err(Error::VolInUseError(vol, owners));
+        }
+
+        let target = self.dataset(name);
+        let generations = self.generations(&target)?;
+        let restored = match generation {
+            Some(gen) if generations.iter().any(|g| g == gen) => gen.to_string(),
+            Some(gen) => return Err(Error::NoSuchGenerationError(vol, gen.to_string())),
+            None => match generations.last() {
+                Some(gen) => gen.clone(),
+                None => return Err(Error::NoGenerationsError(vol)),
+            },
+        };
+
+        let now = Utc::now().format("%Y%m%d%H%M%S%6f");
+        let generation = format!("gen-{}", now);
+        let source = format!("{}/{}", target, restored);
+        // Not a volume name, so it doesn't show up as one in the meantime
+        let restoring = format!("{}.restoring", target);
+        let retired = format!("{}/{}", restoring, generation);
+        let zfs = &*self.backend;
+
+        let mut tx = Transaction::new(name);
+        tx.step(
+            "take out generation",
+            || zfs.rename(&source, &restoring),
+            || zfs.rename(&restoring, &source),
+        )?;
+        // So that the current contents can be pruned as a generation later on
+        if zfs.get(&restoring, &["origin"])?["origin"] != "-" {
+            tx.step(
+                "promote",
+                || zfs.promote(&restoring),
+                || zfs.promote(&target),
+            )?;
+        }
+        for gen in self.generations(&target)? {
+            let from = format!("{}/{}", target, gen);
+            let to = format!("{}/{}", restoring, gen);
+            let (undo_from, undo_to) = (to.clone(), from.clone());
+            tx.step(
+                "move generation",
+                || zfs.rename(&from, &to),
+                move || zfs.rename(&undo_from, &undo_to),
+            )?;
+        }
+        tx.step(
+            "retire",
+            || zfs.rename(&target, &retired),
+            || zfs.rename(&retired, &target),
+        )?;
+        tx.step(
+            "rename",
+            || zfs.rename(&restoring, &target),
+            || zfs.rename(&target, &restoring),
+        )?;
+        tx.commit();
+
+        Ok((restored, generation))
+    }
+
+    // The volume `name` was cloned from
+    fn origin_of(&self, name: &str) -> Result<String, Error> {
+        let origin = self.backend.get(&self.dataset(name), &["origin"])?["origin"].clone();
+        let fs = origin.split('@').next().unwrap_or_default();
+        if Path::new(fs).parent() == Some(&self.root) {
+            Ok(basename(fs))
+        } else {
+            Err(Error::NotACloneError(name.to_string()))
+        }
+    }
+
+    fn do_lineage(&self, name: &str) -> Result<(Vec<LineageInfo>, Vec<LineageInfo>), Error> {
+        let vol = sanitize_vol(name);
+        // Fails if there is no such volume
+        self.backend.get(&self.dataset(name), &["name"])?;
+
+        let known = self
+            .backend
+            .list(
+                &self.root.to_string_lossy(),
+                Kind::Filesystem,
+                Some(1),
+                &["name", PROP_SOURCE_VOLUME, PROP_SOURCE_SNAPSHOT, PROP_REASON],
+            )?
+            .into_iter()
+            .skip(1)
+            .filter(|props| is_volume(&basename(&props["name"])))
+            .map(|props| {
+                let prop = |name: &str| props.get(name).filter(|x| *x != "-").cloned();
+                let info = LineageInfo {
+                    name: basename(&props["name"]),
+                    source_volume: prop(PROP_SOURCE_VOLUME),
+                    source_snapshot: prop(PROP_SOURCE_SNAPSHOT),
+                    reason: prop(PROP_REASON),
+                };
+                (info.name.clone(), info)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut ancestors = Vec::new();
+        let mut next = known.get(&vol).and_then(|info| info.source_volume.clone());
+        while let Some(name) = next.take() {
+            // Guard against cycles, in case a name has been reused
+            if name == vol || ancestors.iter().any(|a: &LineageInfo| a.name == name) {
+                break;
+            }
+            let info = known.get(&name).cloned().unwrap_or_else(|| LineageInfo {
+                name,
+                source_volume: None,
+                source_snapshot: None,
+                reason: None,
+            });
+            next = info.source_volume.clone();
+            ancestors.push(info);
+        }
+
+        let mut descendants = Vec::new();
+        let mut parents = vec![vol.clone()];
+        while !parents.is_empty() {
+            let children = known
+                .values()
+                .filter(|info| info.source_volume.as_ref().map_or(false, |s| parents.contains(s)))
+                .filter(|info| info.name != vol && !descendants.contains(*info))
+                .cloned()
+                .collect::<Vec<_>>();
+            parents = children.iter().map(|info| info.name.clone()).collect();
+            descendants.extend(children);
+        }
+
+        Ok((ancestors, descendants))
+    }
+
+    // The previous generations of a committed volume, oldest first
+    fn generations(&self, dataset: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .backend
+            .list(dataset, Kind::Filesystem, Some(1), &["name"])?
+            .into_iter()
+            .skip(1)
+            .map(|props| basename(&props["name"]))
+            .filter(|name| name.starts_with("gen-"))
+            .sorted()
+            .collect())
+    }
+}
+
+// The `clones` property of a snapshot, as volume names
+fn clone_names(clones: &str) -> Vec<String> {
+    clones
+        .split(',')
+        .filter(|clone| !clone.is_empty() && *clone != "-")
+        .map(basename)
+        .collect()
+}
+
+// Make `props` of `dataset` local if they are in `local`, inherited otherwise
+fn set_local(
+    zfs: &dyn ZfsBackend,
+    dataset: &str,
+    props: &[&str],
+    local: &Properties,
+) -> Result<(), Error> {
+    for prop in props {
+        match local.get(*prop) {
+            Some(value) => zfs.set(dataset, prop, value)?,
+            None => match NOT_INHERITABLE.iter().find(|(p, _)| p == prop) {
+                Some((_, default)) => zfs.set(dataset, prop, default)?,
+                None => zfs.inherit(dataset, prop)?,
+            },
+        }
+    }
+    Ok(())
+}
+
+impl AdminApi for Zfs {
+    fn snapshot(&self, rq: SnapshotRequest) -> Result<SnapshotResponse, ErrorResponse> {
+        info!("Admin.Snapshot: {:?}", rq);
+        self.do_snapshot(&rq.name, rq.snapshot.as_ref().map(String::as_str))
+            .map_err(|e| {
+                error!("Admin.Snapshot: {:?}", e);
+                e.into()
+            })
+            .map(|snapshot| SnapshotResponse { snapshot })
+    }
+
+    fn rollback(&self, rq: RollbackRequest) -> Result<(), ErrorResponse> {
+        info!("Admin.Rollback: {:?}", rq);
+        self.do_rollback(&rq.name, &rq.snapshot, rq.destroy_newer)
+            .map_err(|e| {
+                error!("Admin.Rollback: {:?}", e);
+                e.into()
+            })
+    }
+
+    fn snapshots(&self, rq: SnapshotsRequest) -> Result<SnapshotsResponse, ErrorResponse> {
+        info!("Admin.Snapshots: {:?}", rq);
+        self.do_snapshots(&rq.name)
+            .map_err(|e| {
+                error!("Admin.Snapshots: {:?}", e);
+                e.into()
+            })
+            .map(|snapshots| SnapshotsResponse { snapshots })
+    }
+
+    fn destroy_snapshot(&self, rq: DestroySnapshotRequest) -> Result<(), ErrorResponse> {
+        info!("Admin.DestroySnapshot: {:?}", rq);
+        self.do_destroy_snapshot(&rq.name, &rq.snapshot)
+            .map_err(|e| {
+                error!("Admin.DestroySnapshot: {:?}", e);
+                e.into()
+            })
+    }
+
+    fn set_quota(&self, rq: SetQuotaRequest) -> Result<(), ErrorResponse> {
+        info!("Admin.SetQuota: {:?}", rq);
+        self.do_set_quota(&rq.name, &rq.refquota).map_err(|e| {
+            error!("Admin.SetQuota: {:?}", e);
+            e.into()
+        })
+    }
+
+    fn clones(&self, rq: ClonesRequest) -> Result<ClonesResponse, ErrorResponse> {
+        info!("Admin.Clones: {:?}", rq);
+        self.do_clones(&rq.name)
+            .map_err(|e| {
+                error!("Admin.Clones: {:?}", e);
+                e.into()
+            })
+            .map(|clones| ClonesResponse { clones })
+    }
+
+    fn force_unmount(
+        &self,
+        rq: ForceUnmountRequest,
+    ) -> Result<ForceUnmountResponse, ErrorResponse> {
+        info!("Admin.ForceUnmount: {:?}", rq);
+        self.do_force_unmount(&rq.name)
+            .map_err(|e| {
+                error!("Admin.ForceUnmount: {:?}", e);
+                e.into()
+            })
+            .map(|owners| ForceUnmountResponse { owners })
+    }
+
+    fn reconcile(&self, rq: ReconcileRequest) -> Result<ReconcileResponse, ErrorResponse> {
+        info!("Admin.Reconcile: {:?}", rq);
+        Zfs::reconcile(self, rq.dry_run)
+            .map_err(|e| {
+                error!("Admin.Reconcile: {:?}", e);
+                e.into()
+            })
+            .map(|findings| ReconcileResponse { findings })
+    }
+
+    fn commit(&self, rq: CommitRequest) -> Result<CommitResponse, ErrorResponse> {
+        info!("Admin.Commit: {:?}", rq);
+        self.do_commit(&rq.name, rq.keep.unwrap_or(1))
+            .map_err(|e| {
+                error!("Admin.Commit: {:?}", e);
+                e.into()
+            })
+            .map(|(origin, generation)| CommitResponse { origin, generation })
+    }
+
+    fn restore(&self, rq: RestoreRequest) -> Result<RestoreResponse, ErrorResponse> {
+        info!("Admin.Restore: {:?}", rq);
+        self.do_restore(&rq.name, rq.generation.as_deref())
+            .map_err(|e| {
+                error!("Admin.Restore: {:?}", e);
+                e.into()
+            })
+            .map(|(restored, generation)| RestoreResponse {
+                restored,
+                generation,
+            })
+    }
+
+    fn lineage(&self, rq: LineageRequest) -> Result<LineageResponse, ErrorResponse> {
+        info!("Admin.Lineage: {:?}", rq);
+        self.do_lineage(&rq.name)
+            .map_err(|e| {
+                error!("Admin.Lineage: {:?}", e);
+                e.into()
+            })
+            .map(|(ancestors, descendants)| LineageResponse {
+                ancestors,
+                descendants,
+            })
+    }
+
+    fn mounts(&self) -> Result<MountsResponse, ErrorResponse> {
+        info!("Admin.Mounts");
+        self.mounts
+            .all()
+            .map_err(|e| {
+                error!("Admin.Mounts: {:?}", e);
+                e.into()
+            })
+            .map(|table| MountsResponse {
+                mounts: table
+                    .into_iter()
+                    .map(|(vol, owners)| (vol, owners.into_iter().sorted().collect()))
+                    .collect(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::api::{CreateRequest, MountRequest, RemoveRequest, VolumePlugin};
+    use crate::backend::ZfsBackend;
+    use crate::config::WithClones;
+    use crate::testing::Fixture;
+
+    fn create(fx: &Fixture, name: &str, opts: HashMap<String, String>) {
+        fx.create(CreateRequest {
+            name: name.to_string(),
+            options: Some(opts),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_rollback() {
+        let fx = Fixture::new();
+        create(&fx, "vol", HashMap::new());
+
+        let snap = fx
+            .snapshot(SnapshotRequest {
+                name: "vol".to_string(),
+                snapshot: Some("good:1".to_string()),
+            })
+            .unwrap()
+            .snapshot;
+        assert_eq!(snap, "good_1");
+        fx.mem.write("tank/zocker/vol", 100).unwrap();
+
+        fx.mount(MountRequest {
+            name: "vol".to_string(),
+            id: "c1".to_string(),
+        })
+        .unwrap();
+        let rollback = || {
+            fx.rollback(RollbackRequest {
+                name: "vol".to_string(),
+                snapshot: "good:1".to_string(),
+                destroy_newer: false,
+            })
+        };
+        assert!(rollback().is_err());
+
+        let owners = fx
+            .force_unmount(ForceUnmountRequest {
+                name: "vol".to_string(),
+            })
+            .unwrap()
+            .owners;
+        assert_eq!(owners, vec!["c1"]);
+        assert!(fx.mounts.owners("vol").unwrap().is_empty());
+
+        rollback().unwrap();
+        assert_eq!(
+            fx.mem.get("tank/zocker/vol", &["referenced"]).unwrap()["referenced"],
+            "0"
+        );
+
+        // Unnamed snapshots taken in quick succession don't collide
+        for _ in 0..2 {
+            fx.snapshot(SnapshotRequest {
+                name: "vol".to_string(),
+                snapshot: None,
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_list_and_destroy_snapshots() {
+        let fx = Fixture::new();
+        create(&fx, "vol", HashMap::new());
+        for snapshot in &["a", "b"] {
+            fx.snapshot(SnapshotRequest {
+                name: "vol".to_string(),
+                snapshot: Some((*snapshot).to_string()),
+            })
+            .unwrap();
+        }
+        let mut opts = HashMap::new();
+        opts.insert("from".to_string(), "vol@a".to_string());
+        create(&fx, "branch", opts);
+
+        let snapshots = || {
+            fx.snapshots(SnapshotsRequest {
+                name: "vol".to_string(),
+            })
+            .unwrap()
+            .snapshots
+        };
+        let listed = snapshots();
+        assert_eq!(
+            listed.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert_eq!(listed[0].clones, vec!["branch"]);
+        assert!(listed[1].clones.is_empty());
+
+        let destroy = |snapshot: &str| fx.do_destroy_snapshot("vol", snapshot);
+        match destroy("a") {
+            Err(Error::HasClonesError(snap, clones)) => {
+                assert_eq!(snap, "vol@a");
+                assert_eq!(clones, vec!["branch"]);
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match destroy("c") {
+            Err(Error::NoSuchSnapshotError(vol, snap)) => {
+                assert_eq!((vol.as_str(), snap.as_str()), ("vol", "c"))
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+        destroy("b").unwrap();
+        assert_eq!(snapshots().len(), 1);
+    }
+
+    #[test]
+    fn test_commit() {
+        let fx = Fixture::new();
+        create(&fx, "master", HashMap::new());
+        fx.mem.write("tank/zocker/master", 1024).unwrap();
+        fx.mem.set("tank/zocker", "recordsize", "1048576").unwrap();
+        fx.mem.set("tank/zocker/master", "logbias", "throughput").unwrap();
+
+        let commit = |name: &str| {
+            fx.commit(CommitRequest {
+                name: name.to_string(),
+                keep: None,
+            })
+        };
+        let prop = |ds: &str, prop: &str| fx.mem.get(ds, &[prop]).unwrap()[prop].clone();
+        let mut opts = HashMap::new();
+        opts.insert("from".to_string(), "master".to_string());
+        opts.insert("ttl".to_string(), "1h".to_string());
+
+        assert!(commit("master").is_err());
+
+        create(&fx, "branch", opts.clone());
+        fx.mem.write("tank/zocker/branch", 2048).unwrap();
+        fx.mount(MountRequest {
+            name: "master".to_string(),
+            id: "c1".to_string(),
+        })
+        .unwrap();
+        assert!(commit("branch").is_err());
+        fx.force_unmount(ForceUnmountRequest {
+            name: "master".to_string(),
+        })
+        .unwrap();
+
+        let rsp = commit("branch").unwrap();
+        assert_eq!(rsp.origin, "master");
+        assert!(!fx.exists("branch").unwrap());
+        assert_eq!(prop("tank/zocker/master", "referenced"), "3072");
+        assert_eq!(prop("tank/zocker/master", "origin"), "-");
+        // The clone's own options and lineage don't carry over
+        assert_eq!(prop("tank/zocker/master", PROP_TTL), "-");
+        assert_eq!(prop("tank/zocker/master", PROP_SOURCE_VOLUME), "-");
+        // Only the origin's own properties do, inherited ones stay inherited
+        assert_eq!(prop("tank/zocker/master", "logbias"), "throughput");
+        let local = fx.mem.get_local("tank/zocker/master", &["logbias", "recordsize"]);
+        assert_eq!(local.unwrap().keys().collect::<Vec<_>>(), vec!["logbias"]);
+        fx.mem.set("tank/zocker", "recordsize", "16384").unwrap();
+        assert_eq!(prop("tank/zocker/master", "recordsize"), "16384");
+        let old = format!("tank/zocker/master/{}", rsp.generation);
+        assert_eq!(prop(&old, "referenced"), "1024");
+
+        // Only the most recent generation is kept
+        create(&fx, "branch", opts);
+        let rsp = commit("branch").unwrap();
+        assert!(fx.mem.get(&old, &["name"]).is_err());
+        assert_eq!(
+            prop(&format!("tank/zocker/master/{}", rsp.generation), "referenced"),
+            "3072"
+        );
+        assert_eq!(fx.list().unwrap().volumes.len(), 1);
+    }
+
+    #[test]
+    fn test_restore() {
+        let fx = Fixture::new();
+        create(&fx, "master", HashMap::new());
+        fx.mem.write("tank/zocker/master", 1024).unwrap();
+
+        let restore = |generation: Option<&str>| {
+            fx.restore(RestoreRequest {
+                name: "master".to_string(),
+                generation: generation.map(String::from),
+            })
+        };
+        let referenced = |ds: &str| fx.mem.get(ds, &["referenced"]).unwrap()["referenced"].clone();
+        assert_eq!(restore(None).unwrap_err().err, "Volume master has no previous generations");
+
+        let mut opts = HashMap::new();
+        opts.insert("from".to_string(), "master".to_string());
+        create(&fx, "branch", opts);
+        fx.mem.write("tank/zocker/branch", 2048).unwrap();
+        let committed = fx
+            .commit(CommitRequest {
+                name: "branch".to_string(),
+                keep: None,
+            })
+            .unwrap()
+            .generation;
+        assert_eq!(
+            restore(Some("gen-0")).unwrap_err().err,
+            "Volume master has no generation gen-0"
+        );
+        fx.mount(MountRequest {
+            name: "master".to_string(),
+            id: "c1".to_string(),
+        })
+        .unwrap();
+        assert!(restore(None).is_err());
+        fx.force_unmount(ForceUnmountRequest {
+            name: "master".to_string(),
+        })
+        .unwrap();
+
+        let rsp = restore(None).unwrap();
+        assert_eq!(rsp.restored, committed);
+        assert_eq!(referenced("tank/zocker/master"), "1024");
+        assert_eq!(referenced(&format!("tank/zocker/master/{}", rsp.generation)), "3072");
+        assert!(fx.mem.get(&format!("tank/zocker/master/{}", committed), &["name"]).is_err());
+        assert_eq!(fx.list().unwrap().volumes.len(), 1);
+
+        // And back again
+        let rsp = restore(Some(&rsp.generation)).unwrap();
+        assert_eq!(referenced("tank/zocker/master"), "3072");
+        assert_eq!(referenced(&format!("tank/zocker/master/{}", rsp.generation)), "1024");
+        // Which leaves the previous contents as prunable as after a commit
+        fx.mem
+            .destroy(&format!("tank/zocker/master/{}", rsp.generation), Destroy::Recursive)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_lineage() {
+        let fx = Fixture::new();
+        let from = |vol: &str, reason: Option<&str>| {
+            let mut opts = HashMap::new();
+            opts.insert("from".to_string(), vol.to_string());
+            if let Some(reason) = reason {
+                opts.insert("reason".to_string(), reason.to_string());
+            }
+            opts
+        };
+        create(&fx, "master", HashMap::new());
+        create(&fx, "branch", from("master", Some("pr-1")));
+        create(&fx, "leaf", from("branch", None));
+        create(&fx, "other", from("master", None));
+        create(&fx, "unrelated", HashMap::new());
+
+        let lineage = |name: &str| {
+            let rsp = fx
+                .lineage(LineageRequest {
+                    name: name.to_string(),
+                })
+                .unwrap();
+            let names = |infos: Vec<LineageInfo>| {
+                infos.into_iter().map(|info| info.name).collect::<Vec<_>>()
+            };
+            (names(rsp.ancestors), names(rsp.descendants))
+        };
+        assert_eq!(
+            lineage("master"),
+            (vec![], vec!["branch".to_string(), "other".to_string(), "leaf".to_string()])
+        );
+        assert_eq!(
+            lineage("leaf"),
+            (vec!["branch".to_string(), "master".to_string()], vec![])
+        );
+
+        let rsp = fx
+            .lineage(LineageRequest {
+                name: "leaf".to_string(),
+            })
+            .unwrap();
+        assert_eq!(rsp.ancestors[0].source_volume, Some("master".to_string()));
+        assert!(rsp.ancestors[0].source_snapshot.is_some());
+        assert_eq!(rsp.ancestors[0].reason, Some("pr-1".to_string()));
+        assert_eq!(rsp.ancestors[1].reason, None);
+
+        // Lineage is recorded, so it outlives the origin
+        fx.remove(RemoveRequest {
+            name: "branch".to_string(),
+        })
+        .unwrap_err();
+        let zfs = fx.zfs.clone().with_clones(WithClones::Promote);
+        zfs.remove(RemoveRequest {
+            name: "branch".to_string(),
+        })
+        .unwrap();
+        assert_eq!(lineage("leaf"), (vec!["branch".to_string()], vec![]));
+
+        assert!(fx
+            .lineage(LineageRequest {
+                name: "missing".to_string()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_set_quota_and_clones() {
+        let fx = Fixture::new();
+        create(&fx, "master", HashMap::new());
+        let mut opts = HashMap::new();
+        opts.insert("from".to_string(), "master".to_string());
+        create(&fx, "branch", opts);
+
+        fx.set_quota(SetQuotaRequest {
+            name: "master".to_string(),
+            refquota: "1GiB".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            fx.mem.get("tank/zocker/master", &["refquota"]).unwrap()["refquota"],
+            "1073741824"
+        );
+        assert!(fx
+            .set_quota(SetQuotaRequest {
+                name: "master".to_string(),
+                refquota: "lots".to_string(),
+            })
+            .is_err());
+
+        let clones = fx
+            .clones(ClonesRequest {
+                name: "master".to_string(),
+            })
+            .unwrap()
+            .clones;
+        assert_eq!(clones.len(), 1);
+        assert_eq!(clones[0].name, "branch");
+    }
+}
@@ -0,0 +1,384 @@
+use std::fmt;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::thread;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::backend::{Destroy, Kind, ZfsBackend};
+use crate::config::de_duration;
+use crate::zfs::{basename, is_volume, Error, OptsError, Zfs, PROP_AUTO_SNAPSHOT};
+
+/// Snapshots taken automatically are named `auto-<schedule>-<time>`, so that
+/// we can tell when the last one was taken after a restart.
+const PREFIX: &str = "auto-";
+const TIME_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// How often to check whether automatic snapshots are due.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    /// `null` disables automatic snapshots.
+    #[serde(deserialize_with = "de_duration")]
+    pub interval: Option<Duration>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: Some(Duration::minutes(5)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Schedule {
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl Schedule {
+    fn name(self) -> &'static str {
+        match self {
+            Schedule::Hourly => "hourly",
+            Schedule::Daily => "daily",
+            Schedule::Weekly => "weekly",
+        }
+    }
+
+    /// The start of the period `t` falls into. One snapshot is taken per
+    /// period.
+    fn period_start(self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let day = t.date().and_hms(0, 0, 0);
+        match self {
+            Schedule::Hourly => t.date().and_hms(t.hour(), 0, 0),
+            Schedule::Daily => day,
+            Schedule::Weekly => day - Duration::days(i64::from(t.weekday().num_days_from_monday())),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = OptsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(Schedule::Hourly),
+            "daily" => Ok(Schedule::Daily),
+            "weekly" => Ok(Schedule::Weekly),
+            _ => Err(OptsError("Invalid auto-snapshot schedule specified")),
+        }
+    }
+}
+
+/// The `auto-snapshot=` option of a volume: which snapshots to take, and how
+/// many of each to keep, eg. `hourly:24,daily:7`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spec(pub Vec<(Schedule, usize)>);
+
+impl FromStr for Spec {
+    type Err = OptsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut schedules: Vec<(Schedule, usize)> = Vec::new();
+        for part in s.split(',') {
+            let mut parts = part.trim().splitn(2, ':');
+            let schedule = parts.next().unwrap_or_default().parse()?;
+            let keep = parts
+                .next()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .ok_or(OptsError("Invalid auto-snapshot retention specified"))?;
+            if schedules.iter().any(|(s, _)| *s == schedule) {
+                return Err(OptsError("Duplicate auto-snapshot schedule specified"));
+            }
+            schedules.push((schedule, keep));
+        }
+        Ok(Spec(schedules))
+    }
+}
+
+impl Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let spec = self
+            .0
+            .iter()
+            .map(|(schedule, keep)| format!("{}:{}", schedule.name(), keep))
+            .join(",");
+        write!(f, "{}", spec)
+    }
+}
+
+/// An automatic snapshot of a volume.
+#[derive(Debug)]
+struct Taken {
+    snapshot: String,
+    schedule: Schedule,
+    at: DateTime<Utc>,
+}
+
+impl Zfs {
+    /// Take the automatic snapshots due at `now` of all volumes which have
+    /// any, and prune old ones.
+    pub fn auto_snapshot(&self, now: DateTime<Utc>) -> Result<(), Error> {
+        let vols = self
+            .backend
+            .list(
+                &self.root.to_string_lossy(),
+                Kind::Filesystem,
+                Some(1),
+                &["name", PROP_AUTO_SNAPSHOT],
+            )?
+            .into_iter()
+            .skip(1)
+            .filter_map(|props| {
+                let vol = basename(&props["name"]);
+                let spec = props[PROP_AUTO_SNAPSHOT].parse::<Spec>().ok()?;
+                if is_volume(&vol) {
+                    Some((vol, spec))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // One volume failing shouldn't keep the others from being snapshotted
+        for (vol, spec) in vols {
+            if let Err(e) = self.auto_snapshot_vol(&vol, &spec, now) {
+                error!("Auto-snapshot: volume {}: {:?}", vol, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn auto_snapshot_vol(&self, vol: &str, spec: &Spec, now: DateTime<Utc>) -> Result<(), Error> {
+        let _lock = self.lock(&[vol])?;
+        let dataset = self.dataset(vol);
+
+        // Nothing to preserve if the volume hasn't changed since its last
+        // snapshot
+        if self.backend.get(&dataset, &["written"])?["written"] == "0" {
+            return Ok(());
+        }
+
+        let taken = auto_snapshots(&*self.backend, &dataset)?;
+        for (schedule, keep) in &spec.0 {
+            let previous = taken
+                .iter()
+                .filter(|t| t.schedule == *schedule)
+                .collect::<Vec<_>>();
+            if previous
+                .last()
+                .map_or(false, |last| last.at >= schedule.period_start(now))
+            {
+                continue;
+            }
+
+            let snapshot = format!(
+                "{}@{}{}-{}",
+                dataset,
+                PREFIX,
+                schedule.name(),
+                now.format(TIME_FORMAT)
+            );
+            self.backend.snapshot(&snapshot)?;
+            info!("Auto-snapshot: took {}", basename(&snapshot));
+
+            // Snapshots which have been cloned go away along with their last
+            // clone
+            for old in previous.iter().rev().skip(keep - 1) {
+                match self.backend.destroy(&old.snapshot, Destroy::Deferred) {
+                    Ok(()) => info!("Auto-snapshot: pruned {}", basename(&old.snapshot)),
+                    Err(e) => warn!("Auto-snapshot: failed to prune {}: {:?}", old.snapshot, e),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The automatic snapshots of `dataset` which aren't pending destruction,
+/// oldest first.
+fn auto_snapshots(zfs: &dyn ZfsBackend, dataset: &str) -> Result<Vec<Taken>, Error> {
+    let prefix = format!("{}@{}", dataset, PREFIX);
+    Ok(zfs
+        .list(dataset, Kind::Snapshot, Some(1), &["name", "defer_destroy"])?
+        .into_iter()
+        .filter(|props| props["defer_destroy"] != "on")
+        .filter_map(|props| {
+            if !props["name"].starts_with(&prefix) {
+                return None;
+            }
+            let mut parts = props["name"][prefix.len()..].splitn(2, '-');
+            let schedule = parts.next()?.parse().ok()?;
+            let at = Utc.datetime_from_str(parts.next()?, TIME_FORMAT).ok()?;
+            Some(Taken {
+                snapshot: props["name"].clone(),
+                schedule,
+                at,
+            })
+        })
+        .sorted_by_key(|t| t.at)
+        .collect())
+}
+
+/// How the automatic snapshots of `dataset` are doing, for `Volume.Status`.
+pub(crate) fn status(
+    zfs: &dyn ZfsBackend,
+    dataset: &str,
+    spec: &Spec,
+) -> Result<Vec<(String, String)>, Error> {
+    let taken = auto_snapshots(zfs, dataset)?;
+    Ok(spec
+        .0
+        .iter()
+        .map(|(schedule, keep)| {
+            let mine = taken.iter().filter(|t| t.schedule == *schedule);
+            let count = mine.clone().count();
+            let value = match mine.last() {
+                Some(last) => format!("{}/{}, last {}", count, keep, last.at.to_rfc3339()),
+                None => format!("0/{}", keep),
+            };
+            (format!("auto_snapshot_{}", schedule.name()), value)
+        })
+        .collect())
+}
+
+/// Check for due automatic snapshots every `settings.interval`, in the
+/// background.
+pub fn spawn(zfs: Zfs, settings: Settings) {
+    let interval = match settings.interval.and_then(|i| i.to_std().ok()) {
+        Some(interval) => interval,
+        None => return,
+    };
+
+    thread::Builder::new()
+        .name("auto-snapshot".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            if let Err(e) = zfs.auto_snapshot(Utc::now()) {
+                error!("Auto-snapshot: {:?}", e);
+            }
+        })
+        .expect("Failed to spawn auto-snapshot thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::api::{CreateRequest, GetRequest, VolumePlugin};
+    use crate::testing::Fixture;
+
+    fn at(hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.ymd(2020, 1, 1).and_hms(hour, min, 0)
+    }
+
+    fn snapshots(fx: &Fixture) -> Vec<String> {
+        auto_snapshots(&*fx.mem, "tank/zocker/vol")
+            .unwrap()
+            .into_iter()
+            .map(|t| basename(&t.snapshot))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = "hourly:24, daily:7".parse::<Spec>().unwrap();
+        assert_eq!(spec, Spec(vec![(Schedule::Hourly, 24), (Schedule::Daily, 7)]));
+        assert_eq!(spec.to_string(), "hourly:24,daily:7");
+
+        for invalid in &["", "hourly", "hourly:0", "yearly:1", "daily:1,daily:2"] {
+            assert!(invalid.parse::<Spec>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_period_start() {
+        // 2020-01-01 was a Wednesday
+        let t = Utc.ymd(2020, 1, 1).and_hms(13, 45, 10);
+        assert_eq!(Schedule::Hourly.period_start(t), at(13, 0));
+        assert_eq!(Schedule::Daily.period_start(t), at(0, 0));
+        assert_eq!(
+            Schedule::Weekly.period_start(t),
+            Utc.ymd(2019, 12, 30).and_hms(0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_auto_snapshot() {
+        let fx = Fixture::new();
+        let mut opts = HashMap::new();
+        opts.insert("auto-snapshot".to_string(), "hourly:2,daily:1".to_string());
+        fx.create(CreateRequest {
+            name: "vol".to_string(),
+            options: Some(opts),
+        })
+        .unwrap();
+
+        fx.mem.write("tank/zocker/vol", 1).unwrap();
+        fx.auto_snapshot(at(10, 5)).unwrap();
+        assert_eq!(
+            snapshots(&fx),
+            vec!["vol@auto-hourly-20200101100500", "vol@auto-daily-20200101100500"]
+        );
+
+        // Not due yet, and then unchanged
+        fx.mem.write("tank/zocker/vol", 1).unwrap();
+        fx.auto_snapshot(at(10, 55)).unwrap();
+        assert_eq!(snapshots(&fx).len(), 2);
+        fx.auto_snapshot(at(11, 5)).unwrap();
+        fx.auto_snapshot(at(12, 5)).unwrap();
+        assert_eq!(snapshots(&fx).len(), 3);
+
+        // Only the most recent ones are kept
+        fx.mem.write("tank/zocker/vol", 1).unwrap();
+        fx.auto_snapshot(at(13, 5)).unwrap();
+        assert_eq!(
+            snapshots(&fx),
+            vec![
+                "vol@auto-daily-20200101100500",
+                "vol@auto-hourly-20200101110500",
+                "vol@auto-hourly-20200101130500"
+            ]
+        );
+
+        let status = status(
+            &*fx.mem,
+            "tank/zocker/vol",
+            &"hourly:2,daily:1,weekly:1".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            status,
+            vec![
+                (
+                    "auto_snapshot_hourly".to_string(),
+                    "2/2, last 2020-01-01T13:05:00+00:00".to_string()
+                ),
+                (
+                    "auto_snapshot_daily".to_string(),
+                    "1/1, last 2020-01-01T10:05:00+00:00".to_string()
+                ),
+                ("auto_snapshot_weekly".to_string(), "0/1".to_string()),
+            ]
+        );
+
+        let status = fx
+            .get(GetRequest {
+                name: "vol".to_string(),
+            })
+            .unwrap()
+            .volume
+            .status
+            .unwrap();
+        assert_eq!(status["auto_snapshot_daily"], "1/1, last 2020-01-01T10:05:00+00:00");
+        assert!(status["options"].ends_with(",auto-snapshot=hourly:2,daily:1"));
+    }
+}
@@ -0,0 +1,367 @@
+use std::borrow::BorrowMut;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use users::{
+    get_effective_gid, get_effective_groupname, get_effective_uid, get_effective_username,
+};
+
+use crate::backend::{Destroy, Kind, Properties, ZfsBackend};
+use crate::zfs::Error;
+
+/// Runs the `zfs` command line tool, using `sudo` where delegation via `zfs
+/// allow` isn't possible.
+///
+/// Commands taking longer than `timeout` are killed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cli {
+    timeout: Option<Duration>,
+}
+
+impl Cli {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self { timeout }
+    }
+
+    // `zfs get`, reporting only properties from `source` if given
+    fn zfs_get(
+        &self,
+        name: &str,
+        props: &[&str],
+        source: Option<&str>,
+    ) -> Result<Properties, Error> {
+        let out = ZfsCmd::User.run(self.timeout, |zfs| {
+            let zfs = zfs.args(&["get", "-H", "-p"]);
+            match source {
+                Some(source) => zfs.arg("-s").arg(source),
+                None => zfs,
+            }
+            .args(&["-o", "property,value"])
+            .arg(props.join(","))
+            .arg(name)
+        })?;
+
+        Ok(String::from_utf8_lossy(&out)
+            .lines()
+            .filter_map(|line| {
+                let mut cols = line.splitn(2, '\t');
+                match (cols.next(), cols.next()) {
+                    (Some(k), Some(v)) => Some((k.to_string(), v.to_string())),
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+}
+
+impl ZfsBackend for Cli {
+    fn create(&self, dataset: &str, props: &Properties) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| zfs.arg("create").args(as_args(props)).arg(dataset))
+            .map(|_| ())
+    }
+
+    fn snapshot(&self, snapshot: &str) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| zfs.arg("snapshot").arg(snapshot))
+            .map(|_| ())
+    }
+
+    fn clone_snapshot(
+        &self,
+        snapshot: &str,
+        dataset: &str,
+        props: &Properties,
+    ) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| {
+                zfs.arg("clone")
+                    .args(as_args(props))
+                    .arg(snapshot)
+                    .arg(dataset)
+            })
+            .map(|_| ())
+    }
+
+    fn destroy(&self, name: &str, how: Destroy) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| {
+                let zfs = zfs.arg("destroy");
+                match how {
+                    Destroy::Single => zfs,
+                    Destroy::Recursive => zfs.arg("-r"),
+                    Destroy::Deferred => zfs.arg("-d"),
+                }
+                .arg(name)
+            })
+            .map(|_| ())
+    }
+
+    fn rollback(&self, snapshot: &str, destroy_newer: bool) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| {
+                let zfs = zfs.arg("rollback");
+                if destroy_newer {
+                    zfs.arg("-r")
+                } else {
+                    zfs
+                }
+                .arg(snapshot)
+            })
+            .map(|_| ())
+    }
+
+    fn promote(&self, dataset: &str) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| zfs.arg("promote").arg(dataset))
+            .map(|_| ())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| zfs.arg("rename").arg(from).arg(to))
+            .map(|_| ())
+    }
+
+    fn get(&self, name: &str, props: &[&str]) -> Result<Properties, Error> {
+        self.zfs_get(name, props, None)
+    }
+
+    fn get_local(&self, name: &str, props: &[&str]) -> Result<Properties, Error> {
+        self.zfs_get(name, props, Some("local"))
+    }
+
+    fn set(&self, name: &str, prop: &str, value: &str) -> Result<(), Error> {
+        // ZoL can't delegate mount permissions
+        let zfs_cmd = if prop == "mountpoint" {
+            ZfsCmd::Sudo
+        } else {
+            ZfsCmd::User
+        };
+        zfs_cmd
+            .run(self.timeout, |zfs| zfs.arg("set").arg(format!("{}={}", prop, value)).arg(name))
+            .map(|_| ())
+    }
+
+    fn inherit(&self, name: &str, prop: &str) -> Result<(), Error> {
+        ZfsCmd::User
+            .run(self.timeout, |zfs| zfs.arg("inherit").arg(prop).arg(name))
+            .map(|_| ())
+    }
+
+    fn list(
+        &self,
+        name: &str,
+        kind: Kind,
+        depth: Option<usize>,
+        props: &[&str],
+    ) -> Result<Vec<Properties>, Error> {
+        let out = ZfsCmd::User.run(self.timeout, |zfs| {
+            let zfs = zfs.args(&["list", "-H", "-p", "-r", "-t"]).arg(match kind {
+                Kind::Filesystem => "filesystem",
+                Kind::Snapshot => "snapshot",
+            });
+            match depth {
+                Some(d) => zfs.arg("-d").arg(d.to_string()),
+                None => zfs,
+            }
+            .arg("-o")
+            .arg(props.join(","))
+            .arg(name)
+        })?;
+
+        Ok(String::from_utf8_lossy(&out)
+            .lines()
+            .map(|line| {
+                props
+                    .iter()
+                    .map(|p| (*p).to_string())
+                    .zip(line.split('\t').map(String::from))
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn chown(&self, path: &Path) -> Result<(), Error> {
+        Command::new("sudo")
+            .arg("chown")
+            .arg({
+                // Try hard to use username:groupname instead of uid:gid
+                let mut user = get_effective_username()
+                    .unwrap_or_else(|| get_effective_uid().to_string().into());
+                let group = get_effective_groupname()
+                    .unwrap_or_else(|| get_effective_gid().to_string().into());
+
+                user.push(":");
+                user.push(group);
+                user
+            })
+            .arg(path)
+            .run(self.timeout)
+            .map(|_| ())
+    }
+}
+
+fn as_args(props: &Properties) -> Vec<String> {
+    props
+        .iter()
+        .flat_map(|(k, v)| vec!["-o".to_string(), format!("{}={}", k, v)])
+        .collect()
+}
+
+enum ZfsCmd {
+    Sudo,
+    User,
+}
+
+impl ZfsCmd {
+    fn run<F>(&self, timeout: Option<Duration>, f: F) -> Result<Vec<u8>, Error>
+    where
+        F: FnOnce(&mut Command) -> &mut Command,
+    {
+        let sudo = self.sudo();
+        let mut base = Command::new(if sudo { "sudo" } else { "zfs" });
+        let cmd = {
+            let cmd = if sudo {
+                base.arg("zfs")
+            } else {
+                base.borrow_mut()
+            };
+            f(cmd)
+        };
+        cmd.run(timeout)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sudo(&self) -> bool {
+        if get_effective_uid() == 0 {
+            false
+        } else {
+            match self {
+                ZfsCmd::Sudo => true,
+                ZfsCmd::User => false,
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn sudo(&self) -> bool {
+        false
+    }
+}
+
+trait CommandExt {
+    fn run(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, Error>;
+}
+
+impl CommandExt for Command {
+    fn run(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+        let cmd = format!("{:?}", self);
+        let mut child = self
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::CmdIoError(cmd.clone(), e))?;
+
+        // Drain both pipes while waiting, so the child can't block on a full one
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+
+        let status = match timeout {
+            None => child.wait().map(Some),
+            Some(timeout) => wait_timeout(&mut child, timeout),
+        }
+        .map_err(|e| Error::CmdIoError(cmd.clone(), e))?;
+
+        match status {
+            None => {
+                let timeout = timeout.unwrap_or_default();
+                warn!("{}: timed out after {:?}, killing it", cmd, timeout);
+                if let Err(e) = child.kill() {
+                    // Most likely `sudo`, which we aren't allowed to signal
+                    warn!("{}: failed to kill: {}", cmd, e);
+                }
+                // Reap it whenever it exits, without holding up the caller
+                thread::spawn(move || child.wait());
+                Err(Error::CmdTimeoutError(cmd, timeout))
+            },
+            Some(status) => {
+                let stdout = stdout.join().unwrap_or_default();
+                let stderr = stderr.join().unwrap_or_default();
+                if status.success() {
+                    Ok(stdout)
+                } else {
+                    Err(Error::CmdError(cmd, stderr))
+                }
+            },
+        }
+    }
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+// Wait for `child` to exit, for at most `timeout`. `None` if it didn't.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(backoff.min(deadline - now));
+        backoff = (backoff * 2).min(Duration::from_millis(100));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let out = Command::new("sh")
+            .args(&["-c", "echo out; echo err >&2"])
+            .run(None)
+            .unwrap();
+        assert_eq!(out, b"out\n");
+
+        match Command::new("sh")
+            .args(&["-c", "echo err >&2; exit 1"])
+            .run(Some(Duration::from_secs(5)))
+        {
+            Err(Error::CmdError(_, stderr)) => assert_eq!(stderr, b"err\n"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let start = Instant::now();
+        match Command::new("sleep")
+            .arg("10")
+            .run(Some(Duration::from_millis(100)))
+        {
+            Err(Error::CmdTimeoutError(_, timeout)) => {
+                assert_eq!(timeout, Duration::from_millis(100))
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,887 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::backend::{Destroy, Kind, Properties, ZfsBackend};
+use crate::zfs::Error;
+
+/// Properties which are inherited from the parent dataset if not set
+/// locally, and their defaults at the top of the hierarchy.
+const INHERITABLE: &[(&str, &str)] = &[
+    ("acltype", "off"),
+    ("atime", "on"),
+    ("compression", "off"),
+    ("copies", "1"),
+    ("devices", "on"),
+    ("dnodesize", "legacy"),
+    ("exec", "on"),
+    ("logbias", "latency"),
+    ("primarycache", "all"),
+    ("recordsize", "131072"),
+    ("setuid", "on"),
+    ("sync", "standard"),
+    ("xattr", "on"),
+];
+
+/// Properties which only apply to the dataset they are set on.
+const LOCAL: &[(&str, &str)] = &[
+    ("quota", "0"),
+    ("refquota", "0"),
+    ("refreservation", "0"),
+    ("reservation", "0"),
+];
+
+const READONLY: &[&str] = &[
+    "available",
+    "avail",
+    "clones",
+    "compressratio",
+    "createtxg",
+    "creation",
+    "defer_destroy",
+    "logicalreferenced",
+    "logicalused",
+    "mounted",
+    "name",
+    "origin",
+    "refcompressratio",
+    "referenced",
+    "type",
+    "used",
+    "written",
+];
+
+/// An in-memory simulation of a ZFS pool, good enough to exercise the
+/// volume lifecycle without root privileges or a real pool.
+///
+/// Datasets have no actual contents: the amount of data referenced by them is
+/// simulated via `write`. Setting the `mountpoint` doesn't touch the
+/// filesystem.
+#[derive(Debug)]
+pub struct Memory {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    size: u64,
+    clock: Option<i64>,
+    txg: u64,
+    nodes: BTreeMap<String, Node>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    props: HashMap<String, String>,
+    creation: i64,
+    txg: u64,
+    origin: Option<String>,
+    referenced: u64,
+    written: u64,
+    defer_destroy: bool,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::with_size(1024 * 1024 * 1024 * 10) // 10GiB
+    }
+}
+
+impl Memory {
+    /// A pool with a capacity of `size` bytes.
+    pub fn with_size(size: u64) -> Self {
+        Self {
+            state: Mutex::new(State {
+                size,
+                clock: None,
+                txg: 0,
+                nodes: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Pretend the time is `secs` since the epoch from now on.
+    pub fn set_clock(&self, secs: i64) {
+        self.lock().clock = Some(secs)
+    }
+
+    /// Pretend `bytes` have been written to `dataset`.
+    pub fn write(&self, dataset: &str, bytes: u64) -> Result<(), Error> {
+        let cmd = format!("write {}", dataset);
+        let mut state = self.lock();
+        let avail = state.avail(dataset);
+        let node = state.node_mut(&cmd, dataset)?;
+        if bytes > avail {
+            return Err(fail(&cmd, "Disk quota exceeded"));
+        }
+        node.referenced += bytes;
+        node.written += bytes;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl ZfsBackend for Memory {
+    fn create(&self, dataset: &str, props: &Properties) -> Result<(), Error> {
+        let cmd = format!("zfs create {}", dataset);
+        let mut state = self.lock();
+        state.check_new(&cmd, dataset)?;
+        for k in props.keys() {
+            check_settable(&cmd, k)?;
+        }
+        let node = state.new_node(props.clone(), None, 0);
+        state.nodes.insert(dataset.to_string(), node);
+        Ok(())
+    }
+
+    fn snapshot(&self, snapshot: &str) -> Result<(), Error> {
+        let cmd = format!("zfs snapshot {}", snapshot);
+        let mut state = self.lock();
+        let fs = match split_snapshot(snapshot) {
+            Some((fs, name)) if !name.is_empty() => fs,
+            _ => return Err(fail(&cmd, &format!("invalid snapshot name '{}'", snapshot))),
+        };
+        if state.nodes.contains_key(snapshot) {
+            return Err(fail(
+                &cmd,
+                &format!("cannot create snapshot '{}': dataset already exists", snapshot),
+            ));
+        }
+        let referenced = state.node_mut(&cmd, fs)?.referenced;
+        let node = state.new_node(HashMap::new(), None, referenced);
+        state.nodes.insert(snapshot.to_string(), node);
+        state.node_mut(&cmd, fs)?.written = 0;
+        Ok(())
+    }
+
+    fn clone_snapshot(
+        &self,
+        snapshot: &str,
+        dataset: &str,
+        props: &Properties,
+    ) -> Result<(), Error> {
+        let cmd = format!("zfs clone {} {}", snapshot, dataset);
+        let mut state = self.lock();
+        if split_snapshot(snapshot).is_none() {
+            return Err(fail(&cmd, &format!("'{}' is not a snapshot", snapshot)));
+        }
+        let referenced = state.node_mut(&cmd, snapshot)?.referenced;
+        state.check_new(&cmd, dataset)?;
+        for k in props.keys() {
+            check_settable(&cmd, k)?;
+        }
+        let node = state.new_node(props.clone(), Some(snapshot.to_string()), referenced);
+        state.nodes.insert(dataset.to_string(), node);
+        Ok(())
+    }
+
+    fn destroy(&self, name: &str, how: Destroy) -> Result<(), Error> {
+        let cmd = format!("zfs destroy {}", name);
+        let mut state = self.lock();
+
+        if split_snapshot(name).is_some() {
+            if !state.nodes.contains_key(name) {
+                return Err(fail(
+                    &cmd,
+                    "could not find any snapshots to destroy; check snapshot names.",
+                ));
+            }
+            let clones = state.clones_of(name);
+            if !clones.is_empty() {
+                if how == Destroy::Deferred {
+                    state.node_mut(&cmd, name)?.defer_destroy = true;
+                    return Ok(());
+                }
+                return Err(fail(
+                    &cmd,
+                    &format!(
+                        "cannot destroy '{}': snapshot has dependent clones\nuse '-R' to destroy \
+                         the following datasets:\n{}",
+                        name,
+                        clones.join("\n")
+                    ),
+                ));
+            }
+            state.nodes.remove(name);
+            return Ok(());
+        }
+
+        state.node_mut(&cmd, name)?;
+        let subtree = state
+            .nodes
+            .keys()
+            .filter(|k| is_below(k, name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if how != Destroy::Recursive && !subtree.is_empty() {
+            return Err(fail(
+                &cmd,
+                &format!(
+                    "cannot destroy '{}': filesystem has children\nuse '-r' to destroy the \
+                     following datasets:\n{}",
+                    name,
+                    subtree.join("\n")
+                ),
+            ));
+        }
+        let dependents = subtree
+            .iter()
+            .flat_map(|k| state.clones_of(k))
+            .filter(|clone| clone != name && !is_below(clone, name))
+            .collect::<Vec<_>>();
+        if !dependents.is_empty() {
+            return Err(fail(
+                &cmd,
+                &format!(
+                    "cannot destroy '{}': filesystem has dependent clones\nuse '-R' to destroy \
+                     the following datasets:\n{}",
+                    name,
+                    dependents.join("\n")
+                ),
+            ));
+        }
+
+        let mut origins = Vec::new();
+        for k in subtree.iter().chain(Some(&name.to_string())) {
+            if let Some(node) = state.nodes.remove(k) {
+                origins.extend(node.origin);
+            }
+        }
+        // Deferred snapshots go away with their last clone
+        for origin in origins {
+            let deferred = state
+                .nodes
+                .get(&origin)
+                .map(|snap| snap.defer_destroy)
+                .unwrap_or(false);
+            if deferred && state.clones_of(&origin).is_empty() {
+                state.nodes.remove(&origin);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rollback(&self, snapshot: &str, destroy_newer: bool) -> Result<(), Error> {
+        let cmd = format!("zfs rollback {}", snapshot);
+        let mut state = self.lock();
+        let fs = match split_snapshot(snapshot) {
+            Some((fs, _)) => fs,
+            None => {
+                return Err(fail(
+                    &cmd,
+                    &format!(
+                        "cannot open '{}': operation not applicable to datasets of this type",
+                        snapshot
+                    ),
+                ))
+            },
+        };
+        let (txg, referenced) = {
+            let snap = state.node_mut(&cmd, snapshot)?;
+            (snap.txg, snap.referenced)
+        };
+
+        let newer = state
+            .nodes
+            .iter()
+            .filter(|(k, node)| {
+                split_snapshot(k).map(|(of, _)| of == fs).unwrap_or(false) && node.txg > txg
+            })
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        if !newer.is_empty() {
+            if !destroy_newer {
+                return Err(fail(
+                    &cmd,
+                    &format!(
+                        "cannot rollback to '{}': more recent snapshots or bookmarks exist\nuse \
+                         '-r' to force deletion of the following snapshots and bookmarks:\n{}",
+                        snapshot,
+                        newer.join("\n")
+                    ),
+                ));
+            }
+            for snap in &newer {
+                let clones = state.clones_of(snap);
+                if !clones.is_empty() {
+                    return Err(fail(
+                        &cmd,
+                        &format!(
+                            "cannot destroy '{}': snapshot has dependent clones\nuse '-R' to \
+                             destroy the following datasets:\n{}",
+                            snap,
+                            clones.join("\n")
+                        ),
+                    ));
+                }
+            }
+            for snap in &newer {
+                state.nodes.remove(snap);
+            }
+        }
+
+        let node = state.node_mut(&cmd, fs)?;
+        node.referenced = referenced;
+        node.written = 0;
+        Ok(())
+    }
+
+    fn promote(&self, dataset: &str) -> Result<(), Error> {
+        let cmd = format!("zfs promote {}", dataset);
+        let mut state = self.lock();
+        let origin = match state.node_mut(&cmd, dataset)?.origin.clone() {
+            Some(origin) => origin,
+            None => {
+                return Err(fail(
+                    &cmd,
+                    &format!("cannot promote '{}': not a cloned filesystem", dataset),
+                ))
+            },
+        };
+        let origin_fs = split_snapshot(&origin).map(|(fs, _)| fs.to_string()).unwrap();
+        let txg = state.node_mut(&cmd, &origin)?.txg;
+
+        // The origin's snapshots up to and including the one `dataset` was
+        // cloned from move over
+        let moving = state
+            .nodes
+            .iter()
+            .filter_map(|(k, node)| match split_snapshot(k) {
+                Some((fs, snap)) if fs == origin_fs && node.txg <= txg => {
+                    Some((k.clone(), format!("{}@{}", dataset, snap)))
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if let Some((_, conflict)) = moving.iter().find(|(_, to)| state.nodes.contains_key(to)) {
+            return Err(fail(
+                &cmd,
+                &format!("cannot promote '{}': snapshot {} already exists", dataset, conflict),
+            ));
+        }
+        for (from, to) in &moving {
+            state.rename_node(from, to);
+        }
+
+        let promoted_origin = state.node_mut(&cmd, &origin_fs)?.origin.take();
+        let new_origin = moving.iter().find(|(from, _)| *from == origin).unwrap().1.clone();
+        state.node_mut(&cmd, &origin_fs)?.origin = Some(new_origin);
+        state.node_mut(&cmd, dataset)?.origin = promoted_origin;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        let cmd = format!("zfs rename {} {}", from, to);
+        let mut state = self.lock();
+        state.node_mut(&cmd, from)?;
+        if split_snapshot(from).is_some() || split_snapshot(to).is_some() {
+            return Err(fail(&cmd, "renaming snapshots is not supported"));
+        }
+        if is_below(to, from) {
+            return Err(fail(
+                &cmd,
+                &format!("cannot rename '{}': new dataset name cannot be a descendant", from),
+            ));
+        }
+        state.check_new(&cmd, to)?;
+
+        let subtree = state
+            .nodes
+            .keys()
+            .filter(|k| *k == from || is_below(k, from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for k in subtree {
+            let renamed = format!("{}{}", to, &k[from.len()..]);
+            state.rename_node(&k, &renamed);
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str, props: &[&str]) -> Result<Properties, Error> {
+        let cmd = format!("zfs get {} {}", props.join(","), name);
+        let state = self.lock();
+        if !state.nodes.contains_key(name) {
+            return Err(does_not_exist(&cmd, name));
+        }
+        props
+            .iter()
+            .map(|prop| Ok(((*prop).to_string(), state.prop(&cmd, name, prop)?)))
+            .collect()
+    }
+
+    fn get_local(&self, name: &str, props: &[&str]) -> Result<Properties, Error> {
+        let cmd = format!("zfs get -s local {} {}", props.join(","), name);
+        let state = self.lock();
+        if !state.nodes.contains_key(name) {
+            return Err(does_not_exist(&cmd, name));
+        }
+        let mut local = Properties::new();
+        for prop in props {
+            state.prop(&cmd, name, prop)?;
+            if let Some(value) = state.local(name, prop) {
+                local.insert((*prop).to_string(), value);
+            }
+        }
+        Ok(local)
+    }
+
+    fn set(&self, name: &str, prop: &str, value: &str) -> Result<(), Error> {
+        let cmd = format!("zfs set {}={} {}", prop, value, name);
+        check_settable(&cmd, prop)?;
+        let mut state = self.lock();
+        let is_snapshot = split_snapshot(name).is_some();
+        let node = state.node_mut(&cmd, name)?;
+        if is_snapshot && !is_user_prop(prop) {
+            return Err(fail(
+                &cmd,
+                "this property can not be modified for snapshots",
+            ));
+        }
+        node.props.insert(prop.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn inherit(&self, name: &str, prop: &str) -> Result<(), Error> {
+        let cmd = format!("zfs inherit {} {}", prop, name);
+        check_settable(&cmd, prop)?;
+        if LOCAL.iter().any(|(p, _)| *p == prop) {
+            return Err(fail(&cmd, &format!("'{}' property cannot be inherited", prop)));
+        }
+        let mut state = self.lock();
+        state.node_mut(&cmd, name)?.props.remove(prop);
+        Ok(())
+    }
+
+    fn list(
+        &self,
+        name: &str,
+        kind: Kind,
+        depth: Option<usize>,
+        props: &[&str],
+    ) -> Result<Vec<Properties>, Error> {
+        let cmd = format!("zfs list {}", name);
+        let state = self.lock();
+        if !state.nodes.contains_key(name) {
+            return Err(does_not_exist(&cmd, name));
+        }
+
+        let level = |k: &str| {
+            let fs = split_snapshot(k).map(|(fs, _)| fs).unwrap_or(k);
+            fs.matches('/').count() - name.matches('/').count()
+        };
+        let mut names = state
+            .nodes
+            .iter()
+            .filter(|(k, _)| *k == name || is_below(k, name))
+            .filter(|(k, _)| match kind {
+                Kind::Filesystem => split_snapshot(k).is_none(),
+                Kind::Snapshot => split_snapshot(k).is_some(),
+            })
+            .filter(|(k, _)| depth.map(|d| level(k) <= d).unwrap_or(true))
+            .map(|(k, node)| (k.clone(), node.txg))
+            .collect::<Vec<_>>();
+        // Snapshots are listed in the order they were taken
+        if kind == Kind::Snapshot {
+            names.sort_by_key(|(_, txg)| *txg);
+        }
+
+        names
+            .into_iter()
+            .map(|(k, _)| {
+                props
+                    .iter()
+                    .map(|prop| Ok(((*prop).to_string(), state.prop(&cmd, &k, prop)?)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn chown(&self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl State {
+    fn now(&self) -> i64 {
+        self.clock.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("SystemTime before UNIX epoch!")
+                .as_secs() as i64
+        })
+    }
+
+    fn new_node(
+        &mut self,
+        props: HashMap<String, String>,
+        origin: Option<String>,
+        referenced: u64,
+    ) -> Node {
+        self.txg += 1;
+        Node {
+            props,
+            creation: self.now(),
+            txg: self.txg,
+            origin,
+            referenced,
+            written: 0,
+            defer_destroy: false,
+        }
+    }
+
+    fn node_mut(&mut self, cmd: &str, name: &str) -> Result<&mut Node, Error> {
+        self.nodes
+            .get_mut(name)
+            .ok_or_else(|| does_not_exist(cmd, name))
+    }
+
+    fn check_new(&self, cmd: &str, dataset: &str) -> Result<(), Error> {
+        if self.nodes.contains_key(dataset) {
+            return Err(fail(
+                cmd,
+                &format!("cannot create '{}': dataset already exists", dataset),
+            ));
+        }
+        match parent_of(dataset) {
+            Some(parent) if !self.nodes.contains_key(parent) => Err(fail(
+                cmd,
+                &format!("cannot create '{}': parent does not exist", dataset),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Move node `from` to `to`, keeping the clones of it pointing at it
+    fn rename_node(&mut self, from: &str, to: &str) {
+        if let Some(node) = self.nodes.remove(from) {
+            self.nodes.insert(to.to_string(), node);
+        }
+        for node in self.nodes.values_mut() {
+            if node.origin.as_ref().map(|o| o == from).unwrap_or(false) {
+                node.origin = Some(to.to_string());
+            }
+        }
+    }
+
+    fn clones_of(&self, snapshot: &str) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.origin.as_ref().map(|o| o == snapshot).unwrap_or(false))
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+
+    fn used(&self, name: &str) -> u64 {
+        self.nodes
+            .iter()
+            .filter(|(k, _)| *k == name || is_below(k, name))
+            .filter(|(k, _)| split_snapshot(k).is_none())
+            .map(|(_, node)| node.referenced)
+            .sum()
+    }
+
+    fn avail(&self, name: &str) -> u64 {
+        let pool = name.split('/').next().unwrap_or(name);
+        let free = self.size.saturating_sub(self.used(pool));
+        match self.local(name, "refquota").and_then(|q| q.parse::<u64>().ok()) {
+            Some(quota) if quota > 0 => {
+                let referenced = self.nodes.get(name).map(|n| n.referenced).unwrap_or(0);
+                free.min(quota.saturating_sub(referenced))
+            },
+            _ => free,
+        }
+    }
+
+    fn local(&self, name: &str, prop: &str) -> Option<String> {
+        self.nodes.get(name).and_then(|n| n.props.get(prop).cloned())
+    }
+
+    /// The value of an inheritable property, and the dataset it comes from.
+    fn inherited(&self, name: &str, prop: &str) -> Option<(String, String)> {
+        let mut cur = Some(name);
+        while let Some(ds) = cur {
+            if let Some(v) = self.local(ds, prop) {
+                return Some((v, ds.to_string()));
+            }
+            cur = parent_of(ds);
+        }
+        None
+    }
+
+    fn mountpoint(&self, name: &str) -> String {
+        match self.inherited(name, "mountpoint") {
+            None => format!("/{}", name),
+            Some((v, ref from)) if from == name || v == "none" || v == "legacy" => v,
+            Some((v, from)) => format!("{}{}", v, &name[from.len()..]),
+        }
+    }
+
+    fn prop(&self, cmd: &str, name: &str, prop: &str) -> Result<String, Error> {
+        let node = &self.nodes[name];
+        let snapshot = split_snapshot(name);
+        let fs = snapshot.map(|(fs, _)| fs).unwrap_or(name);
+
+        let value = match prop {
+            "name" => name.to_string(),
+            "type" => if snapshot.is_some() { "snapshot" } else { "filesystem" }.to_string(),
+            "creation" => node.creation.to_string(),
+            "createtxg" => node.txg.to_string(),
+            "used" if snapshot.is_some() => "0".to_string(),
+            "used" => self.used(name).to_string(),
+            "referenced" | "logicalreferenced" | "logicalused" => node.referenced.to_string(),
+            "written" => node.written.to_string(),
+            "available" | "avail" if snapshot.is_some() => "-".to_string(),
+            "available" | "avail" => self.avail(name).to_string(),
+            "compressratio" | "refcompressratio" => "1.00".to_string(),
+            "origin" => node.origin.clone().unwrap_or_else(|| "-".to_string()),
+            "clones" => self.clones_of(name).join(","),
+            "defer_destroy" => if node.defer_destroy { "on" } else { "off" }.to_string(),
+            "mountpoint" if snapshot.is_some() => "-".to_string(),
+            "mountpoint" => self.mountpoint(name),
+            "mounted" => match self.mountpoint(name).as_str() {
+                "none" | "legacy" => "no",
+                _ => "yes",
+            }
+            .to_string(),
+            _ if is_user_prop(prop) => self
+                .inherited(name, prop)
+                .map(|(v, _)| v)
+                .unwrap_or_else(|| "-".to_string()),
+            _ => {
+                if let Some((_, def)) = INHERITABLE.iter().find(|(p, _)| *p == prop) {
+                    self.inherited(fs, prop)
+                        .map(|(v, _)| v)
+                        .unwrap_or_else(|| (*def).to_string())
+                } else if let Some((_, def)) = LOCAL.iter().find(|(p, _)| *p == prop) {
+                    self.local(fs, prop).unwrap_or_else(|| (*def).to_string())
+                } else {
+                    return Err(invalid_property(cmd, prop));
+                }
+            },
+        };
+        Ok(value)
+    }
+}
+
+fn check_settable(cmd: &str, prop: &str) -> Result<(), Error> {
+    if READONLY.contains(&prop) {
+        return Err(fail(cmd, &format!("'{}' is readonly", prop)));
+    }
+    let known = is_user_prop(prop)
+        || prop == "mountpoint"
+        || INHERITABLE.iter().chain(LOCAL).any(|(p, _)| *p == prop);
+    if known {
+        Ok(())
+    } else {
+        Err(invalid_property(cmd, prop))
+    }
+}
+
+fn is_user_prop(prop: &str) -> bool {
+    prop.contains(':')
+}
+
+fn split_snapshot(name: &str) -> Option<(&str, &str)> {
+    let at = name.find('@')?;
+    Some((&name[..at], &name[at + 1..]))
+}
+
+fn parent_of(dataset: &str) -> Option<&str> {
+    dataset.rfind('/').map(|slash| &dataset[..slash])
+}
+
+// Whether `k` is a descendant of `name`, or a snapshot of it or any of its
+// descendants
+fn is_below(k: &str, name: &str) -> bool {
+    k.len() > name.len()
+        && k.starts_with(name)
+        && (k[name.len()..].starts_with('/') || k[name.len()..].starts_with('@'))
+}
+
+fn fail(cmd: &str, msg: &str) -> Error {
+    Error::CmdError(cmd.to_string(), msg.as_bytes().to_vec())
+}
+
+fn does_not_exist(cmd: &str, name: &str) -> Error {
+    fail(cmd, &format!("cannot open '{}': dataset does not exist", name))
+}
+
+fn invalid_property(cmd: &str, prop: &str) -> Error {
+    fail(cmd, &format!("bad property list: invalid property '{}'", prop))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::props;
+
+    fn fixture() -> Memory {
+        let mem = Memory::with_size(1000);
+        mem.create("tank", &props(&[("mountpoint", "/mnt")])).unwrap();
+        mem.create("tank/zocker", &props(&[("exec", "off")]))
+            .unwrap();
+        mem
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let mem = fixture();
+        mem.create("tank/zocker/vol", &Properties::new()).unwrap();
+
+        let got = mem
+            .get("tank/zocker/vol", &["mountpoint", "exec", "setuid", "zockervols:x"])
+            .unwrap();
+        assert_eq!(got["mountpoint"], "/mnt/zocker/vol");
+        assert_eq!(got["exec"], "off");
+        assert_eq!(got["setuid"], "on");
+        assert_eq!(got["zockervols:x"], "-");
+
+        mem.set("tank/zocker", "zockervols:x", "y").unwrap();
+        mem.set("tank/zocker/vol", "mountpoint", "none").unwrap();
+        let got = mem
+            .get("tank/zocker/vol", &["mountpoint", "mounted", "zockervols:x"])
+            .unwrap();
+        assert_eq!(got["mountpoint"], "none");
+        assert_eq!(got["mounted"], "no");
+        assert_eq!(got["zockervols:x"], "y");
+
+        assert!(mem.get("tank/zocker/vol", &["bogus"]).is_err());
+        assert!(mem.set("tank/zocker/vol", "used", "1").is_err());
+    }
+
+    #[test]
+    fn test_quota() {
+        let mem = fixture();
+        mem.create("tank/zocker/vol", &props(&[("refquota", "100")]))
+            .unwrap();
+        mem.write("tank/zocker/vol", 60).unwrap();
+        assert_eq!(mem.get("tank/zocker/vol", &["avail"]).unwrap()["avail"], "40");
+        assert!(mem.write("tank/zocker/vol", 60).is_err());
+        assert_eq!(mem.get("tank/zocker", &["used"]).unwrap()["used"], "60");
+    }
+
+    #[test]
+    fn test_clones() {
+        let mem = fixture();
+        mem.create("tank/zocker/origin", &Properties::new()).unwrap();
+        mem.write("tank/zocker/origin", 10).unwrap();
+        mem.snapshot("tank/zocker/origin@1").unwrap();
+        mem.clone_snapshot("tank/zocker/origin@1", "tank/zocker/clone", &Properties::new())
+            .unwrap();
+
+        let got = mem
+            .get("tank/zocker/clone", &["origin", "referenced", "written"])
+            .unwrap();
+        assert_eq!(got["origin"], "tank/zocker/origin@1");
+        assert_eq!(got["referenced"], "10");
+        assert_eq!(got["written"], "0");
+        assert_eq!(
+            mem.get("tank/zocker/origin@1", &["clones"]).unwrap()["clones"],
+            "tank/zocker/clone"
+        );
+
+        // Neither the origin nor the snapshot can go while the clone exists
+        assert!(mem
+            .destroy("tank/zocker/origin", Destroy::Recursive)
+            .is_err());
+        assert!(mem
+            .destroy("tank/zocker/origin@1", Destroy::Single)
+            .is_err());
+
+        // .. unless deferred
+        mem.destroy("tank/zocker/origin@1", Destroy::Deferred)
+            .unwrap();
+        assert!(mem.get("tank/zocker/origin@1", &["name"]).is_ok());
+        mem.destroy("tank/zocker/clone", Destroy::Single).unwrap();
+        assert!(mem.get("tank/zocker/origin@1", &["name"]).is_err());
+
+        mem.destroy("tank/zocker/origin", Destroy::Single).unwrap();
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mem = fixture();
+        mem.create("tank/zocker/vol", &Properties::new()).unwrap();
+        mem.write("tank/zocker/vol", 10).unwrap();
+        mem.snapshot("tank/zocker/vol@1").unwrap();
+        mem.write("tank/zocker/vol", 20).unwrap();
+        mem.snapshot("tank/zocker/vol@2").unwrap();
+        mem.write("tank/zocker/vol", 30).unwrap();
+
+        mem.rollback("tank/zocker/vol@2", false).unwrap();
+        assert_eq!(mem.get("tank/zocker/vol", &["referenced"]).unwrap()["referenced"], "30");
+
+        assert!(mem.rollback("tank/zocker/vol@1", false).is_err());
+        mem.rollback("tank/zocker/vol@1", true).unwrap();
+        assert_eq!(mem.get("tank/zocker/vol", &["referenced"]).unwrap()["referenced"], "10");
+        assert!(mem.get("tank/zocker/vol@2", &["name"]).is_err());
+    }
+
+    #[test]
+    fn test_promote_and_rename() {
+        let mem = fixture();
+        mem.create("tank/zocker/origin", &Properties::new()).unwrap();
+        mem.snapshot("tank/zocker/origin@1").unwrap();
+        mem.snapshot("tank/zocker/origin@2").unwrap();
+        mem.snapshot("tank/zocker/origin@3").unwrap();
+        mem.clone_snapshot("tank/zocker/origin@2", "tank/zocker/clone", &Properties::new())
+            .unwrap();
+        mem.clone_snapshot("tank/zocker/origin@1", "tank/zocker/other", &Properties::new())
+            .unwrap();
+
+        assert!(mem.promote("tank/zocker/origin").is_err());
+        mem.promote("tank/zocker/clone").unwrap();
+        let origin = |ds: &str| mem.get(ds, &["origin"]).unwrap()["origin"].clone();
+        assert_eq!(origin("tank/zocker/clone"), "-");
+        assert_eq!(origin("tank/zocker/origin"), "tank/zocker/clone@2");
+        assert_eq!(origin("tank/zocker/other"), "tank/zocker/clone@1");
+        assert!(mem.get("tank/zocker/origin@3", &["name"]).is_ok());
+        assert!(mem.get("tank/zocker/origin@2", &["name"]).is_err());
+
+        assert!(mem
+            .rename("tank/zocker/clone", "tank/zocker/clone/x")
+            .is_err());
+        assert!(mem.rename("tank/zocker/clone", "tank/zocker/other").is_err());
+        mem.rename("tank/zocker/origin", "tank/zocker/clone/old")
+            .unwrap();
+        mem.rename("tank/zocker/clone", "tank/zocker/origin")
+            .unwrap();
+        assert_eq!(origin("tank/zocker/origin/old"), "tank/zocker/origin@2");
+        assert_eq!(origin("tank/zocker/other"), "tank/zocker/origin@1");
+        assert!(mem.get("tank/zocker/origin/old@3", &["name"]).is_ok());
+    }
+
+    #[test]
+    fn test_list() {
+        let mem = fixture();
+        mem.create("tank/zocker/a", &Properties::new()).unwrap();
+        mem.create("tank/zocker/a/nested", &Properties::new()).unwrap();
+        mem.create("tank/zocker/b", &Properties::new()).unwrap();
+        mem.snapshot("tank/zocker/b@2").unwrap();
+        mem.snapshot("tank/zocker/b@1").unwrap();
+
+        let names = |kind, depth| {
+            mem.list("tank/zocker", kind, depth, &["name"])
+                .unwrap()
+                .into_iter()
+                .map(|p| p["name"].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(Kind::Filesystem, Some(1)),
+            vec!["tank/zocker", "tank/zocker/a", "tank/zocker/b"]
+        );
+        assert_eq!(names(Kind::Filesystem, None).len(), 4);
+        assert_eq!(
+            names(Kind::Snapshot, None),
+            vec!["tank/zocker/b@2", "tank/zocker/b@1"]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::Path;
+
+use crate::zfs::Error;
+
+pub mod cli;
+pub mod memory;
+
+pub use self::cli::Cli;
+pub use self::memory::Memory;
+
+/// ZFS properties by name, as printed by `zfs get -p`.
+pub type Properties = HashMap<String, String>;
+
+/// How `destroy` should treat dependents of the dataset or snapshot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Destroy {
+    /// Fail if there are any.
+    Single,
+    /// Destroy child datasets and snapshots along with it (`-r`).
+    Recursive,
+    /// Destroy a snapshot once its last clone is gone (`-d`).
+    Deferred,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Filesystem,
+    Snapshot,
+}
+
+/// The ZFS operations zockervols is built upon.
+///
+/// Errors are reported the way the `zfs` command line tool would, ie. as
+/// `Error::CmdError` carrying the message `zfs` would print to stderr.
+pub trait ZfsBackend: Debug + Send + Sync {
+    /// Create the filesystem `dataset` with the given properties.
+    fn create(&self, dataset: &str, props: &Properties) -> Result<(), Error>;
+
+    /// Create the snapshot `snapshot`, given as `dataset@name`.
+    fn snapshot(&self, snapshot: &str) -> Result<(), Error>;
+
+    /// Create the filesystem `dataset` as a clone of `snapshot`.
+    fn clone_snapshot(&self, snapshot: &str, dataset: &str, props: &Properties)
+        -> Result<(), Error>;
+
+    /// Destroy the filesystem or snapshot `name`.
+    fn destroy(&self, name: &str, how: Destroy) -> Result<(), Error>;
+
+    /// Roll the filesystem back to `snapshot`. Unless `destroy_newer` is
+    /// `true`, this fails if there are more recent snapshots.
+    fn rollback(&self, snapshot: &str, destroy_newer: bool) -> Result<(), Error>;
+
+    /// Make the clone `dataset` independent of its origin, taking over the
+    /// origin's snapshots up to the one it was cloned from. The former origin
+    /// becomes a clone of `dataset` instead.
+    fn promote(&self, dataset: &str) -> Result<(), Error>;
+
+    /// Rename the filesystem `from` to `to`, along with its descendants and
+    /// snapshots.
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error>;
+
+    /// Get the values of `props` for the filesystem or snapshot `name`.
+    fn get(&self, name: &str, props: &[&str]) -> Result<Properties, Error>;
+
+    /// Get those of `props` which are set on `name` itself, as opposed to
+    /// inherited or defaults.
+    fn get_local(&self, name: &str, props: &[&str]) -> Result<Properties, Error>;
+
+    /// Set `prop` on the filesystem or snapshot `name`. Setting the
+    /// `mountpoint` of a filesystem mounts or unmounts it.
+    fn set(&self, name: &str, prop: &str, value: &str) -> Result<(), Error>;
+
+    /// Clear `prop` on `name`, reverting it to the inherited value.
+    fn inherit(&self, name: &str, prop: &str) -> Result<(), Error>;
+
+    /// Get `props` for `name` and its descendants of type `kind`, up to
+    /// `depth` levels below `name` (unlimited if `None`).
+    fn list(
+        &self,
+        name: &str,
+        kind: Kind,
+        depth: Option<usize>,
+        props: &[&str],
+    ) -> Result<Vec<Properties>, Error>;
+
+    /// Hand the mounted directory `path` over to the user the daemon runs as.
+    fn chown(&self, path: &Path) -> Result<(), Error>;
+}
@@ -0,0 +1,206 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::admin::*;
+use crate::api::*;
+
+/// Talks to a running daemon over its plugin and admin sockets.
+#[derive(Clone, Debug)]
+pub struct Client {
+    socket: PathBuf,
+    admin_socket: PathBuf,
+}
+
+impl Client {
+    pub fn new(socket: PathBuf, admin_socket: PathBuf) -> Self {
+        Self {
+            socket,
+            admin_socket,
+        }
+    }
+
+    fn call<Rq, Rsp>(&self, socket: &Path, endpoint: &str, rq: &Rq) -> Result<Rsp, ErrorResponse>
+    where
+        Rq: Serialize,
+        Rsp: DeserializeOwned,
+    {
+        let body = serde_json::to_vec(rq).map_err(|e| e.to_string())?;
+        let mut stream = UnixStream::connect(socket)
+            .map_err(|e| format!("Can't connect to {}: {}", socket.display(), e))?;
+
+        // HTTP/1.0 keeps the server from using chunked encoding
+        write!(
+            stream,
+            "POST /{} HTTP/1.0\r\n\
+             Host: localhost\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             \r\n",
+            endpoint,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+
+        let mut rsp = Vec::new();
+        stream.read_to_end(&mut rsp)?;
+        parse_response(&rsp)
+    }
+
+    fn plugin<Rq: Serialize, Rsp: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        rq: &Rq,
+    ) -> Result<Rsp, ErrorResponse> {
+        self.call(&self.socket, endpoint, rq)
+    }
+
+    fn admin<Rq: Serialize, Rsp: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        rq: &Rq,
+    ) -> Result<Rsp, ErrorResponse> {
+        self.call(&self.admin_socket, endpoint, rq)
+    }
+}
+
+// The daemon replies to failed requests with an `ErrorResponse`, the way
+// Docker expects it.
+fn parse_response<T: DeserializeOwned>(rsp: &[u8]) -> Result<T, ErrorResponse> {
+    let rsp = String::from_utf8_lossy(rsp);
+    let mut parts = rsp.splitn(2, "\r\n\r\n");
+    let status_line = parts.next().and_then(|head| head.lines().next()).unwrap_or("");
+    let body = parts.next().unwrap_or("");
+
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("Unexpected response: {} {}", status_line, body.trim()).into());
+    }
+
+    let value: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    if let Some(err) = value.get("Err").and_then(Value::as_str) {
+        if !err.is_empty() {
+            return Err(err.into());
+        }
+    }
+    serde_json::from_value(value).map_err(|e| e.to_string().into())
+}
+
+impl VolumePlugin for Client {
+    fn create(&self, rq: CreateRequest) -> Result<(), ErrorResponse> {
+        self.plugin(CREATE, &rq)
+    }
+
+    fn remove(&self, rq: RemoveRequest) -> Result<(), ErrorResponse> {
+        self.plugin(REMOVE, &rq)
+    }
+
+    fn mount(&self, rq: MountRequest) -> Result<MountResponse, ErrorResponse> {
+        self.plugin(MOUNT, &rq)
+    }
+
+    fn path(&self, rq: PathRequest) -> Result<PathResponse, ErrorResponse> {
+        self.plugin(PATH, &rq)
+    }
+
+    fn unmount(&self, rq: UnmountRequest) -> Result<(), ErrorResponse> {
+        self.plugin(UNMOUNT, &rq)
+    }
+
+    fn get(&self, rq: GetRequest) -> Result<GetResponse, ErrorResponse> {
+        self.plugin(GET, &rq)
+    }
+
+    fn list(&self) -> Result<ListResponse, ErrorResponse> {
+        self.plugin(LIST, &())
+    }
+
+    // Only of interest to Docker
+    fn capabilities(&self) -> CapabilitiesResponse {
+        CapabilitiesResponse {
+            capabilities: Capabilities {
+                scope: Scope::Local,
+            },
+        }
+    }
+}
+
+impl AdminApi for Client {
+    fn snapshot(&self, rq: SnapshotRequest) -> Result<SnapshotResponse, ErrorResponse> {
+        self.admin(SNAPSHOT, &rq)
+    }
+
+    fn rollback(&self, rq: RollbackRequest) -> Result<(), ErrorResponse> {
+        self.admin(ROLLBACK, &rq)
+    }
+
+    fn snapshots(&self, rq: SnapshotsRequest) -> Result<SnapshotsResponse, ErrorResponse> {
+        self.admin(SNAPSHOTS, &rq)
+    }
+
+    fn destroy_snapshot(&self, rq: DestroySnapshotRequest) -> Result<(), ErrorResponse> {
+        self.admin(DESTROY_SNAPSHOT, &rq)
+    }
+
+    fn set_quota(&self, rq: SetQuotaRequest) -> Result<(), ErrorResponse> {
+        self.admin(SET_QUOTA, &rq)
+    }
+
+    fn clones(&self, rq: ClonesRequest) -> Result<ClonesResponse, ErrorResponse> {
+        self.admin(CLONES, &rq)
+    }
+
+    fn force_unmount(
+        &self,
+        rq: ForceUnmountRequest,
+    ) -> Result<ForceUnmountResponse, ErrorResponse> {
+        self.admin(FORCE_UNMOUNT, &rq)
+    }
+
+    fn reconcile(&self, rq: ReconcileRequest) -> Result<ReconcileResponse, ErrorResponse> {
+        self.admin(RECONCILE, &rq)
+    }
+
+    fn mounts(&self) -> Result<MountsResponse, ErrorResponse> {
+        self.admin(MOUNTS, &())
+    }
+
+    fn commit(&self, rq: CommitRequest) -> Result<CommitResponse, ErrorResponse> {
+        self.admin(COMMIT, &rq)
+    }
+
+    fn restore(&self, rq: RestoreRequest) -> Result<RestoreResponse, ErrorResponse> {
+        self.admin(RESTORE, &rq)
+    }
+
+    fn lineage(&self, rq: LineageRequest) -> Result<LineageResponse, ErrorResponse> {
+        self.admin(LINEAGE, &rq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let ok = b"HTTP/1.0 200 OK\r\ncontent-type: application/json\r\n\r\n{\"Mountpoint\":\"/x\"}";
+        let rsp: PathResponse = parse_response(ok).unwrap();
+        assert_eq!(rsp.mountpoint, "/x");
+
+        let unit = b"HTTP/1.0 200 OK\r\n\r\nnull";
+        parse_response::<()>(unit).unwrap();
+
+        let err = b"HTTP/1.0 200 OK\r\n\r\n{\"Err\":\"Volume foo is in use by: c1\"}";
+        assert_eq!(
+            parse_response::<()>(err).unwrap_err().err,
+            "Volume foo is in use by: c1"
+        );
+
+        let not_found = b"HTTP/1.0 404 Not Found\r\n\r\n";
+        assert!(parse_response::<()>(not_found).is_err());
+    }
+}
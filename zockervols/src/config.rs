@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use byte_unit::Byte;
+use chrono::Duration;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::autosnap;
+use crate::gc;
+use crate::options;
+use crate::zfs::Error;
+
+/// The daemon's configuration file.
+///
+/// All settings are optional, a missing file is equivalent to an empty one.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub gc: gc::Policy,
+    pub exec: Exec,
+    pub remove: Remove,
+    pub auto_snapshot: autosnap::Settings,
+    pub options: options::Policy,
+    pub profiles: Profiles,
+}
+
+/// Named sets of volume options, which `profile=` expands into. Options given
+/// alongside it take precedence.
+pub type Profiles = HashMap<String, HashMap<String, String>>;
+
+/// How volumes are removed.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Remove {
+    pub with_clones: WithClones,
+}
+
+/// What to do when removing a volume other volumes have been cloned from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WithClones {
+    /// Fail, listing the clones.
+    Refuse,
+    /// Hand the volume's snapshots over to the most recent clone, so that the
+    /// volume can be destroyed.
+    Promote,
+    /// Hide the volume, and destroy it once the last clone is gone.
+    Defer,
+}
+
+impl Default for WithClones {
+    fn default() -> Self {
+        WithClones::Refuse
+    }
+}
+
+/// How requests involving ZFS commands are executed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Exec {
+    /// Number of threads running such requests concurrently.
+    pub workers: usize,
+    /// Number of requests which may wait for a worker. Further requests are
+    /// refused until one becomes available.
+    pub queue: usize,
+    /// How long a command may run before it is killed, `null` to wait forever.
+    #[serde(deserialize_with = "de_duration")]
+    pub timeout: Option<Duration>,
+    /// How long a request may wait for other operations on the same volume to
+    /// finish, `null` to wait forever.
+    #[serde(deserialize_with = "de_duration")]
+    pub lock_timeout: Option<Duration>,
+    /// How long mounting an `exclusive=on` volume waits for the container
+    /// holding it to unmount it, `null` to wait forever.
+    #[serde(deserialize_with = "de_duration")]
+    pub exclusive_wait: Option<Duration>,
+}
+
+impl Default for Exec {
+    fn default() -> Self {
+        Exec {
+            workers: 8,
+            queue: 64,
+            timeout: Some(Duration::minutes(5)),
+            lock_timeout: Some(Duration::minutes(2)),
+            exclusive_wait: Some(Duration::zero()),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let err = |e: String| Error::ConfigError(path.to_string_lossy().into_owned(), e);
+        match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| err(e.to_string())),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(err(e.to_string())),
+        }
+    }
+}
+
+/// A regular expression matched against volume names.
+#[derive(Clone, Debug)]
+pub struct Pattern(pub Regex);
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Regex::new(&s).map(Pattern).map_err(de::Error::custom)
+    }
+}
+
+const UNITS: &[(&str, i64)] = &[("w", 604_800), ("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)];
+
+/// Parse a duration like "90s", "15m", "12h", "7d" or "2w". A bare number is
+/// taken to be seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or_else(|| s.len());
+    let (n, unit) = s.split_at(split);
+    let n = n.parse::<i64>().ok()?;
+    let secs = match unit {
+        "" => Some(1),
+        _ => UNITS.iter().find(|(u, _)| *u == unit).map(|(_, secs)| *secs),
+    }?;
+    n.checked_mul(secs).map(Duration::seconds)
+}
+
+/// The inverse of `parse_duration`, using the largest unit which represents
+/// `d` exactly.
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.num_seconds();
+    UNITS
+        .iter()
+        .find(|(_, n)| secs % n == 0)
+        .map(|(unit, n)| format!("{}{}", secs / n, unit))
+        .unwrap_or_else(|| format!("{}s", secs))
+}
+
+pub(crate) fn de_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(s) => parse_duration(&s)
+            .map(Some)
+            .ok_or_else(|| de::Error::custom(format!("invalid duration: {}", s))),
+    }
+}
+
+pub(crate) fn de_bytes<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(s) => Byte::from_str(&s)
+            .ok()
+            .and_then(|b| u64::try_from(b.get_bytes()).ok())
+            .map(Some)
+            .ok_or_else(|| de::Error::custom(format!("invalid size: {}", s))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse_duration("90"), Some(Duration::seconds(90)));
+        assert_eq!(parse_duration("15m"), Some(Duration::minutes(15)));
+        assert_eq!(parse_duration("7d"), Some(Duration::days(7)));
+        assert_eq!(parse_duration("2w"), Some(Duration::weeks(2)));
+        assert_eq!(parse_duration("7 days"), None);
+        assert_eq!(parse_duration("d"), None);
+
+        assert_eq!(format_duration(Duration::days(14)), "2w");
+        assert_eq!(format_duration(Duration::hours(36)), "36h");
+        assert_eq!(format_duration(Duration::seconds(61)), "61s");
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "gc": {
+                    "interval": "1h",
+                    "min-free": "10GiB",
+                    "rules": [
+                        { "pattern": "^cache_", "max-age": "7d" },
+                        { "pattern": "^img_", "keep": true }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.gc.interval, Some(Duration::hours(1)));
+        assert_eq!(config.gc.max_age, None);
+        assert_eq!(config.gc.min_free, Some(10 * 1024 * 1024 * 1024));
+        assert_eq!(config.gc.rules.len(), 2);
+        assert!(config.gc.rules[1].keep);
+        assert_eq!(config.exec.workers, 8);
+        assert_eq!(config.exec.timeout, Some(Duration::minutes(5)));
+
+        let config: Config = serde_json::from_str(
+            r#"{ "exec": { "workers": 2, "timeout": null, "lock-timeout": "30s" } }"#,
+        )
+        .unwrap();
+        assert_eq!(config.exec.workers, 2);
+        assert_eq!(config.exec.queue, 64);
+        assert_eq!(config.exec.timeout, None);
+        assert_eq!(config.exec.lock_timeout, Some(Duration::seconds(30)));
+        assert_eq!(config.exec.exclusive_wait, Some(Duration::zero()));
+
+        let config: Config =
+            serde_json::from_str(r#"{ "remove": { "with-clones": "promote" } }"#).unwrap();
+        assert_eq!(config.remove.with_clones, WithClones::Promote);
+        assert_eq!(Config::default().remove.with_clones, WithClones::Refuse);
+        assert!(serde_json::from_str::<Config>(r#"{ "remove": { "with-clones": "x" } }"#).is_err());
+
+        let config: Config =
+            serde_json::from_str(r#"{ "auto-snapshot": { "interval": null } }"#).unwrap();
+        assert_eq!(config.auto_snapshot.interval, None);
+        assert_eq!(
+            Config::default().auto_snapshot.interval,
+            Some(Duration::minutes(5))
+        );
+
+        let config: Config = serde_json::from_str(
+            r#"{ "profiles": { "build-cache": { "exec": "on", "refquota": "15GiB" } } }"#,
+        )
+        .unwrap();
+        assert_eq!(config.profiles["build-cache"]["refquota"], "15GiB");
+        assert!(serde_json::from_str::<Config>(r#"{ "profiles": { "x": ["exec"] } }"#).is_err());
+
+        assert!(serde_json::from_str::<Config>(r#"{ "gc": { "max-age": "forever" } }"#).is_err());
+        assert!(serde_json::from_str::<Config>(r#"{ "gc": { "maxage": "1d" } }"#).is_err());
+    }
+}
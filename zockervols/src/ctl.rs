@@ -0,0 +1,366 @@
+use std::io::Write;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::admin::*;
+use crate::api::*;
+
+/// Subcommands for inspecting and managing volumes, either via the daemon or
+/// by operating on ZFS directly.
+pub fn subcommands() -> Vec<App<'static, 'static>> {
+    let name = Arg::with_name("name")
+        .value_name("VOLUME")
+        .help("The volume name, as known to Docker or as listed by `ls`")
+        .required(true);
+
+    vec![
+        SubCommand::with_name("ls").about("List volumes"),
+        SubCommand::with_name("inspect")
+            .about("Show the details of a volume")
+            .arg(name.clone()),
+        SubCommand::with_name("mounts").about("Show which containers are using which volumes"),
+        SubCommand::with_name("snapshot")
+            .about("Take a snapshot of a volume")
+            .arg(name.clone())
+            .arg(
+                Arg::with_name("snapshot")
+                    .value_name("SNAPSHOT")
+                    .help("Name of the snapshot, defaults to the current time"),
+            ),
+        SubCommand::with_name("snapshots")
+            .about("List the snapshots of a volume")
+            .arg(name.clone()),
+        SubCommand::with_name("destroy-snapshot")
+            .about("Destroy a snapshot of a volume")
+            .arg(name.clone())
+            .arg(Arg::with_name("snapshot").value_name("SNAPSHOT").required(true)),
+        SubCommand::with_name("rollback")
+            .about("Roll an unused volume back to one of its snapshots")
+            .arg(name.clone())
+            .arg(Arg::with_name("snapshot").value_name("SNAPSHOT").required(true))
+            .arg(
+                Arg::with_name("destroy-newer")
+                    .long("destroy-newer")
+                    .help("Destroy snapshots more recent than SNAPSHOT"),
+            ),
+        SubCommand::with_name("commit")
+            .about("Make the contents of a cloned volume the new contents of its origin")
+            .arg(name.clone())
+            .arg(
+                Arg::with_name("keep")
+                    .long("keep")
+                    .value_name("N")
+                    .help("Number of previous generations of the origin to keep [default: 1]"),
+            ),
+        SubCommand::with_name("restore")
+            .about("Make a previous generation of a committed volume its contents again")
+            .arg(name.clone())
+            .arg(
+                Arg::with_name("generation")
+                    .value_name("GENERATION")
+                    .help("Generation to restore, as reported by commit [default: the latest]"),
+            ),
+        SubCommand::with_name("lineage")
+            .about("Show which volumes a volume was cloned from, and which were cloned from it")
+            .arg(name.clone()),
+        SubCommand::with_name("destroy")
+            .about("Remove a volume")
+            .arg(name)
+            .arg(
+                Arg::with_name("force")
+                    .long("force")
+                    .help("Unmount the volume first, even if it is in use"),
+            ),
+    ]
+}
+
+/// Run subcommand `cmd`, writing its output to `out`.
+pub fn run<H>(h: &H, cmd: &str, args: &ArgMatches, out: &mut dyn Write) -> Result<(), ErrorResponse>
+where
+    H: VolumePlugin + AdminApi,
+{
+    let json = args.is_present("json");
+    let name = || args.value_of("name").unwrap_or_default().to_string();
+
+    match cmd {
+        "ls" => {
+            let volumes = h.list()?.volumes;
+            if json {
+                return print_json(out, &volumes);
+            }
+
+            let rows = volumes
+                .iter()
+                .sorted_by(|a, b| a.name.cmp(&b.name))
+                .map(|vol| {
+                    let status = |key: &str| {
+                        vol.status
+                            .as_ref()
+                            .and_then(|status| status.get(key))
+                            .cloned()
+                            .unwrap_or_default()
+                    };
+                    vec![
+                        vol.name.clone(),
+                        vol.created_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                        status("used"),
+                        status("refquota"),
+                        status("mounted_by"),
+                    ]
+                })
+                .collect();
+            print_table(out, &["NAME", "CREATED", "USED", "REFQUOTA", "MOUNTED BY"], rows)
+        },
+
+        "inspect" => {
+            let vol = h.get(GetRequest { name: name() })?.volume;
+            if json {
+                return print_json(out, &vol);
+            }
+
+            writeln!(out, "Name:       {}", vol.name)?;
+            writeln!(out, "Mountpoint: {}", vol.mountpoint.unwrap_or_default())?;
+            writeln!(
+                out,
+                "Created:    {}",
+                vol.created_at.map(|t| t.to_rfc3339()).unwrap_or_default()
+            )?;
+            if let Some(status) = vol.status {
+                writeln!(out, "Status:")?;
+                for (key, value) in status.iter().sorted() {
+                    writeln!(out, "  {}: {}", key, value)?;
+                }
+            }
+            Ok(())
+        },
+
+        "mounts" => {
+            let mounts = h.mounts()?.mounts;
+            if json {
+                return print_json(out, &mounts);
+            }
+
+            let rows = mounts
+                .into_iter()
+                .map(|(vol, owners)| vec![vol, owners.join(", ")])
+                .collect();
+            print_table(out, &["VOLUME", "MOUNTED BY"], rows)
+        },
+
+        "snapshot" => {
+            let rsp = h.snapshot(SnapshotRequest {
+                name: name(),
+                snapshot: args.value_of("snapshot").map(String::from),
+            })?;
+            if json {
+                print_json(out, &rsp)
+            } else {
+                writeln!(out, "{}", rsp.snapshot).map_err(|e| e.into())
+            }
+        },
+
+        "snapshots" => {
+            let snapshots = h.snapshots(SnapshotsRequest { name: name() })?.snapshots;
+            if json {
+                return print_json(out, &snapshots);
+            }
+
+            let rows = snapshots
+                .into_iter()
+                .map(|snap| {
+                    vec![
+                        snap.name,
+                        snap.created_at.to_rfc3339(),
+                        snap.used.to_string(),
+                        snap.referenced.to_string(),
+                        snap.clones.join(", "),
+                    ]
+                })
+                .collect();
+            print_table(out, &["NAME", "CREATED", "USED", "REFERENCED", "CLONES"], rows)
+        },
+
+        "destroy-snapshot" => h.destroy_snapshot(DestroySnapshotRequest {
+            name: name(),
+            snapshot: args.value_of("snapshot").unwrap_or_default().to_string(),
+        }),
+
+        "rollback" => h.rollback(RollbackRequest {
+            name: name(),
+            snapshot: args.value_of("snapshot").unwrap_or_default().to_string(),
+            destroy_newer: args.is_present("destroy-newer"),
+        }),
+
+        "commit" => {
+            let keep = match args.value_of("keep") {
+                Some(n) => Some(n.parse().map_err(|_| format!("Invalid --keep: {}", n))?),
+                None => None,
+            };
+            let rsp = h.commit(CommitRequest { name: name(), keep })?;
+            if json {
+                print_json(out, &rsp)
+            } else {
+                writeln!(
+                    out,
+                    "Committed into {}, previous contents kept as {}",
+                    rsp.origin, rsp.generation
+                )
+                .map_err(|e| e.into())
+            }
+        },
+
+        "restore" => {
+            let rsp = h.restore(RestoreRequest {
+                name: name(),
+                generation: args.value_of("generation").map(String::from),
+            })?;
+            if json {
+                print_json(out, &rsp)
+            } else {
+                writeln!(
+                    out,
+                    "Restored {}, previous contents kept as {}",
+                    rsp.restored, rsp.generation
+                )
+                .map_err(|e| e.into())
+            }
+        },
+
+        "lineage" => {
+            let rsp = h.lineage(LineageRequest { name: name() })?;
+            if json {
+                return print_json(out, &rsp);
+            }
+
+            let rows = rsp
+                .ancestors
+                .iter()
+                .map(|info| ("ancestor", info))
+                .chain(rsp.descendants.iter().map(|info| ("descendant", info)))
+                .map(|(relation, info)| {
+                    vec![
+                        info.name.clone(),
+                        relation.to_string(),
+                        info.source_volume.clone().unwrap_or_default(),
+                        info.source_snapshot.clone().unwrap_or_default(),
+                        info.reason.clone().unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            print_table(out, &["NAME", "RELATION", "SOURCE", "SNAPSHOT", "REASON"], rows)
+        },
+
+        "destroy" => {
+            if args.is_present("force") {
+                let owners = h.force_unmount(ForceUnmountRequest { name: name() })?.owners;
+                if !owners.is_empty() && !json {
+                    writeln!(out, "Unmounted from {}", owners.join(", "))?;
+                }
+            }
+            h.remove(RemoveRequest { name: name() })
+        },
+
+        _ => Err(format!("Unknown command {}", cmd).into()),
+    }
+}
+
+fn print_json<T: Serialize>(out: &mut dyn Write, value: &T) -> Result<(), ErrorResponse> {
+    serde_json::to_writer_pretty(&mut *out, value).map_err(|e| e.to_string())?;
+    writeln!(out).map_err(|e| e.into())
+}
+
+fn print_table(
+    out: &mut dyn Write,
+    headers: &[&str],
+    rows: Vec<Vec<String>>,
+) -> Result<(), ErrorResponse> {
+    let widths = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(header.len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+
+    let headers = headers.iter().map(|h| (*h).to_string()).collect();
+    for row in Some(headers).into_iter().chain(rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(col, width)| format!("{:width$}", col, width = width))
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{props, Fixture};
+
+    fn run_cmd(fx: &Fixture, args: &[&str]) -> Result<String, ErrorResponse> {
+        let matches = App::new("zockervols")
+            .arg(Arg::with_name("json").long("json").global(true))
+            .subcommands(subcommands())
+            .get_matches_from(Some("zockervols").into_iter().chain(args.iter().cloned()));
+        let (cmd, args) = matches.subcommand();
+
+        let mut out = Vec::new();
+        run(&fx.zfs, cmd, args.unwrap(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_commands() {
+        let fx = Fixture::new();
+        for (name, quota) in &[("cache:a", "1GiB"), ("b", "2MiB")] {
+            fx.create(CreateRequest {
+                name: (*name).to_string(),
+                options: Some(props(&[("refquota", quota)])),
+            })
+            .unwrap();
+        }
+        fx.mount(MountRequest {
+            name: "cache:a".to_string(),
+            id: "c1".to_string(),
+        })
+        .unwrap();
+
+        let ls = run_cmd(&fx, &["ls"]).unwrap();
+        let lines = ls.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("NAME     CREATED"));
+        assert!(lines[1].starts_with("b  "));
+        assert!(lines[2].starts_with("cache_a  "));
+        assert!(lines[2].ends_with("1024.00 MiB  c1"));
+
+        let inspect = run_cmd(&fx, &["inspect", "b"]).unwrap();
+        assert!(inspect.contains("  refquota: 2.00 MiB\n"));
+
+        let mounts = run_cmd(&fx, &["--json", "mounts"]).unwrap();
+        let mounts: serde_json::Value = serde_json::from_str(&mounts).unwrap();
+        assert_eq!(mounts["cache:a"][0], "c1");
+
+        assert_eq!(run_cmd(&fx, &["snapshot", "b", "s1"]).unwrap(), "s1\n");
+        run_cmd(&fx, &["rollback", "b", "s1"]).unwrap();
+        let snapshots = run_cmd(&fx, &["snapshots", "b"]).unwrap();
+        assert_eq!(snapshots.lines().nth(1).map(|l| l.starts_with("s1  ")), Some(true));
+        run_cmd(&fx, &["destroy-snapshot", "b", "s1"]).unwrap();
+        assert!(run_cmd(&fx, &["rollback", "b", "s1"]).is_err());
+
+        assert!(run_cmd(&fx, &["destroy", "cache:a"]).is_err());
+        assert_eq!(
+            run_cmd(&fx, &["destroy", "--force", "cache:a"]).unwrap(),
+            "Unmounted from c1\n"
+        );
+        assert!(!fx.exists("cache:a").unwrap());
+    }
+}
@@ -0,0 +1,439 @@
+use std::fmt;
+use std::fmt::Display;
+use std::thread;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::api::ErrorResponse;
+use crate::backend::Kind;
+use crate::config::{de_bytes, de_duration, format_duration, parse_duration, Pattern};
+use crate::zfs::{
+    basename, is_volume, last_used, sanitize_vol, Error, Zfs, PROP_INCOMPLETE, PROP_LAST_MOUNTED,
+    PROP_LAST_UNMOUNTED, PROP_TTL,
+};
+
+/// When volumes which aren't in use may be removed.
+///
+/// A volume created with a `ttl=` option is removed once it hasn't been used
+/// for that long. Otherwise, the first rule matching the volume's name
+/// decides, falling back to `max_age`. If the pool has less than `min_free`
+/// bytes available after that, the least recently used volumes are removed
+/// until it has, except for those kept by a rule.
+///
+/// Volumes are destroyed without Docker's involvement, so Docker keeps listing
+/// them until they are removed with `docker volume rm`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Policy {
+    /// How often to collect, `null` disables periodic collection.
+    #[serde(deserialize_with = "de_duration")]
+    pub interval: Option<Duration>,
+    #[serde(deserialize_with = "de_duration")]
+    pub max_age: Option<Duration>,
+    #[serde(deserialize_with = "de_bytes")]
+    pub min_free: Option<u64>,
+    pub rules: Vec<Rule>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            interval: Some(Duration::hours(1)),
+            max_age: None,
+            min_free: None,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Rule {
+    pub pattern: Pattern,
+    #[serde(default, deserialize_with = "de_duration")]
+    pub max_age: Option<Duration>,
+    /// Never remove matching volumes.
+    #[serde(default)]
+    pub keep: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// Unused for longer than the volume's `ttl=` option allows.
+    Ttl,
+    /// Unused for longer than the `max-age` of the policy or a matching rule.
+    MaxAge,
+    /// Removed to bring the pool's available space up to `min-free`.
+    FreeSpace,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Removed,
+    Failed(String),
+    DryRun,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Collected {
+    pub vol: String,
+    pub reason: Reason,
+    /// Seconds since the volume was last used.
+    pub idle: i64,
+    pub outcome: Outcome,
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Ttl => write!(f, "ttl expired"),
+            Reason::MaxAge => write!(f, "max age exceeded"),
+            Reason::FreeSpace => write!(f, "pool is low on space"),
+        }
+    }
+}
+
+impl Display for Collected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "volume {} ({}, unused for {})",
+            self.vol,
+            self.reason,
+            format_duration(Duration::seconds(self.idle))
+        )
+    }
+}
+
+/// What we know about a volume when deciding whether to collect it.
+#[derive(Debug)]
+pub(crate) struct Candidate {
+    pub vol: String,
+    pub last_used: DateTime<Utc>,
+    pub ttl: Option<Duration>,
+    pub used: u64,
+    pub in_use: bool,
+}
+
+impl Zfs {
+    /// Remove volumes according to `policy`. If `dry_run` is `true`, only
+    /// report which volumes would be removed.
+    pub fn gc(&self, policy: &Policy, dry_run: bool) -> Result<Vec<Collected>, Error> {
+        let root = self.root.to_string_lossy();
+        let avail = self.backend.get(&root, &["avail"])?["avail"]
+            .parse()
+            .unwrap_or(0);
+        let candidates = self.candidates()?;
+
+        let collected = select(policy, Utc::now(), avail, &candidates)
+            .into_iter()
+            .map(|(candidate, reason)| {
+                let outcome = if dry_run {
+                    Outcome::DryRun
+                } else {
+                    match self.collect(&candidate.vol) {
+                        Ok(()) => Outcome::Removed,
+                        Err(e) => Outcome::Failed(ErrorResponse::from(e).err),
+                    }
+                };
+                Collected {
+                    vol: candidate.vol.clone(),
+                    reason,
+                    idle: (Utc::now() - candidate.last_used).num_seconds(),
+                    outcome,
+                }
+            })
+            .collect();
+
+        Ok(collected)
+    }
+
+    // The volume may have been mounted since we looked, so check again once
+    // nobody else can touch it.
+    fn collect(&self, vol: &str) -> Result<(), Error> {
+        let _lock = self.lock(&[vol])?;
+        let owners = self.owners_of(vol)?;
+        if !owners.is_empty() {
+            return Err(Error::VolInUseError(vol.to_string(), owners));
+        }
+        self.destroy_volume(vol)
+    }
+
+    fn candidates(&self) -> Result<Vec<Candidate>, Error> {
+        let mounts = self.mounts.all()?;
+        let props = &[
+            "name",
+            "mountpoint",
+            "creation",
+            "used",
+            PROP_TTL,
+            PROP_INCOMPLETE,
+            PROP_LAST_MOUNTED,
+            PROP_LAST_UNMOUNTED,
+        ];
+
+        Ok(self
+            .backend
+            .list(&self.root.to_string_lossy(), Kind::Filesystem, Some(1), props)?
+            .into_iter()
+            .skip(1)
+            .filter(|props| is_volume(&basename(&props["name"])))
+            // Incomplete volumes are reconcile's business
+            .filter(|props| props[PROP_INCOMPLETE] != "on")
+            .map(|props| {
+                let vol = basename(&props["name"]);
+                // The mount table is keyed by the names Docker uses, which
+                // may not be sanitized yet.
+                let in_use = props["mountpoint"] != "none"
+                    || mounts.keys().any(|name| sanitize_vol(name) == vol);
+                Candidate {
+                    last_used: last_used(&props).unwrap_or_else(|| Utc.timestamp(0, 0)),
+                    ttl: parse_duration(&props[PROP_TTL]),
+                    used: props["used"].parse().unwrap_or(0),
+                    in_use,
+                    vol,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Decide which of `candidates` to collect, given the pool has `avail` bytes
+/// available.
+pub(crate) fn select<'a>(
+    policy: &Policy,
+    now: DateTime<Utc>,
+    avail: u64,
+    candidates: &'a [Candidate],
+) -> Vec<(&'a Candidate, Reason)> {
+    let mut lru = candidates.iter().filter(|c| !c.in_use).collect::<Vec<_>>();
+    lru.sort_by(|a, b| (a.last_used, &a.vol).cmp(&(b.last_used, &b.vol)));
+
+    let mut selected = Vec::new();
+    let mut remaining = Vec::new();
+    for c in lru {
+        let rule = policy.rules.iter().find(|rule| rule.pattern.0.is_match(&c.vol));
+        if rule.map(|rule| rule.keep).unwrap_or(false) {
+            continue;
+        }
+
+        let idle = now - c.last_used;
+        let max_age = rule.and_then(|rule| rule.max_age).or(policy.max_age);
+        match (c.ttl, max_age) {
+            (Some(ttl), _) if idle > ttl => selected.push((c, Reason::Ttl)),
+            (None, Some(max_age)) if idle > max_age => selected.push((c, Reason::MaxAge)),
+            _ => remaining.push(c),
+        }
+    }
+
+    if let Some(min_free) = policy.min_free {
+        let mut free = selected.iter().fold(avail, |free, (c, _)| free + c.used);
+        for c in remaining {
+            if free >= min_free {
+                break;
+            }
+            free += c.used;
+            selected.push((c, Reason::FreeSpace));
+        }
+    }
+
+    selected
+}
+
+/// Collect garbage every `policy.interval` in a background thread.
+pub fn spawn(zfs: Zfs, policy: Policy) {
+    let interval = match policy.interval.and_then(|i| i.to_std().ok()) {
+        Some(interval) => interval,
+        None => return,
+    };
+
+    thread::Builder::new()
+        .name("gc".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            match zfs.gc(&policy, false) {
+                Ok(collected) => log_collected(&collected),
+                Err(e) => error!("GC: {:?}", e),
+            }
+        })
+        .expect("Failed to spawn GC thread");
+}
+
+pub fn log_collected(collected: &[Collected]) {
+    for c in collected {
+        match c.outcome {
+            // Docker keeps listing it until then
+            Outcome::Removed => info!(
+                "GC: removed {}, `docker volume rm {}` to drop it from Docker",
+                c, c.vol
+            ),
+            Outcome::DryRun => info!("GC: would remove {}", c),
+            Outcome::Failed(ref e) => error!("GC: failed to remove {}: {}", c, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use regex::Regex;
+
+    use crate::api::{
+        CreateRequest, GetRequest, MountRequest, PathRequest, RemoveRequest, UnmountRequest,
+        VolumePlugin,
+    };
+    use crate::testing::{props, Fixture};
+
+    fn candidate(vol: &str, age_days: i64, used: u64) -> Candidate {
+        Candidate {
+            vol: vol.to_string(),
+            last_used: Utc.timestamp(0, 0) - Duration::days(age_days),
+            ttl: None,
+            used,
+            in_use: false,
+        }
+    }
+
+    fn rule(pattern: &str, max_age: Option<Duration>, keep: bool) -> Rule {
+        Rule {
+            pattern: Pattern(Regex::new(pattern).unwrap()),
+            max_age,
+            keep,
+        }
+    }
+
+    fn selected(selection: Vec<(&Candidate, Reason)>) -> Vec<(&str, Reason)> {
+        selection
+            .into_iter()
+            .map(|(c, reason)| (c.vol.as_str(), reason))
+            .collect()
+    }
+
+    #[test]
+    fn test_select_by_age() {
+        let now = Utc.timestamp(0, 0);
+        let policy = Policy {
+            max_age: Some(Duration::days(7)),
+            rules: vec![
+                rule("^img_", None, true),
+                rule("^cache_", Some(Duration::days(2)), false),
+            ],
+            ..Policy::default()
+        };
+        let candidates = vec![
+            candidate("old", 8, 0),
+            candidate("new", 6, 0),
+            candidate("img_old", 30, 0),
+            candidate("cache_3d", 3, 0),
+            Candidate {
+                ttl: Some(Duration::hours(1)),
+                ..candidate("ephemeral", 1, 0)
+            },
+            Candidate {
+                in_use: true,
+                ..candidate("busy", 30, 0)
+            },
+        ];
+
+        assert_eq!(
+            selected(select(&policy, now, 0, &candidates)),
+            vec![
+                ("old", Reason::MaxAge),
+                ("cache_3d", Reason::MaxAge),
+                ("ephemeral", Reason::Ttl)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_by_free_space() {
+        let now = Utc.timestamp(0, 0);
+        let policy = Policy {
+            max_age: Some(Duration::days(7)),
+            min_free: Some(100),
+            rules: vec![rule("^keep", None, true)],
+            ..Policy::default()
+        };
+        let candidates = vec![
+            candidate("expired", 8, 20),
+            candidate("a", 1, 20),
+            candidate("b", 3, 20),
+            candidate("c", 2, 20),
+            candidate("keep", 6, 100),
+        ];
+
+        assert_eq!(
+            selected(select(&policy, now, 50, &candidates)),
+            vec![
+                ("expired", Reason::MaxAge),
+                ("b", Reason::FreeSpace),
+                ("c", Reason::FreeSpace)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gc() {
+        let fx = Fixture::new();
+        let create = |name: &str, opts: &[(&str, &str)]| {
+            fx.create(CreateRequest {
+                name: name.to_string(),
+                options: Some(props(opts)),
+            })
+            .unwrap()
+        };
+
+        fx.mem.set_clock(1000);
+        create("expired", &[("ttl", "10m")]);
+        create("mounted", &[("ttl", "10m")]);
+        create("recently-used", &[("ttl", "10m")]);
+        create("kept", &[]);
+        fx.mount(MountRequest {
+            name: "mounted".to_string(),
+            id: "c1".to_string(),
+        })
+        .unwrap();
+        fx.mount(MountRequest {
+            name: "recently-used".to_string(),
+            id: "c2".to_string(),
+        })
+        .unwrap();
+        fx.unmount(UnmountRequest {
+            name: "recently-used".to_string(),
+            id: "c2".to_string(),
+        })
+        .unwrap();
+
+        let policy = Policy::default();
+        let collected = fx.gc(&policy, true).unwrap();
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].vol, "expired");
+        assert_eq!(collected[0].outcome, Outcome::DryRun);
+        assert!(fx.exists("expired").unwrap());
+
+        let collected = fx.gc(&policy, false).unwrap();
+        assert_eq!(collected[0].outcome, Outcome::Removed);
+        assert!(!fx.exists("expired").unwrap());
+        // Docker still lists it, until removed there too
+        let get = fx.get(GetRequest {
+            name: "expired".to_string(),
+        });
+        assert_eq!(get.unwrap_err().err, "Volume expired does not exist");
+        let path = fx.path(PathRequest {
+            name: "expired".to_string(),
+        });
+        assert_eq!(path.unwrap_err().err, "Volume expired does not exist");
+        fx.remove(RemoveRequest {
+            name: "expired".to_string(),
+        })
+        .unwrap();
+        assert!(fx.exists("mounted").unwrap());
+        assert!(fx.exists("recently-used").unwrap());
+        assert!(fx.exists("kept").unwrap());
+    }
+}
@@ -1,7 +1,22 @@
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate log;
 
+pub mod admin;
 pub mod api;
+pub mod autosnap;
+pub mod backend;
+pub mod client;
+pub mod config;
+pub mod ctl;
+pub mod gc;
+pub mod locks;
+pub mod mounts;
 pub mod noop;
+pub mod options;
+pub mod pool;
+pub mod reconcile;
 pub mod server;
+#[cfg(test)]
+mod testing;
+pub mod transaction;
 pub mod zfs;
@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+
+use crate::pool;
+use crate::zfs::Error;
+
+/// Exclusive locks on volumes, to serialize operations on the same volume
+/// while letting those on different ones proceed in parallel.
+///
+/// All volumes an operation touches are locked at once, so operations
+/// spanning several of them (like cloning one from another) can't deadlock.
+#[derive(Debug)]
+pub struct Locks {
+    held: Mutex<HashSet<String>>,
+    released: Condvar,
+    timeout: Option<Duration>,
+}
+
+/// Releases the locks it was acquired with when dropped.
+#[derive(Debug)]
+pub struct Guard<'a> {
+    locks: &'a Locks,
+    vols: Vec<String>,
+}
+
+impl Locks {
+    /// Waiting for a lock fails after `timeout`, or never if `None`.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            held: Mutex::new(HashSet::new()),
+            released: Condvar::new(),
+            timeout,
+        }
+    }
+
+    pub fn acquire(&self, vols: &[&str]) -> Result<Guard, Error> {
+        let vols = vols
+            .iter()
+            .map(|vol| (*vol).to_string())
+            .unique()
+            .collect::<Vec<_>>();
+        // The set is only ever modified in one go, so it's fine to carry on
+        // after a panic elsewhere.
+        let held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut held = if vols.iter().any(|vol| held.contains(vol)) {
+            // Without holding up requests for other volumes
+            pool::blocking(|| self.wait(held, &vols))?
+        } else {
+            held
+        };
+        held.extend(vols.iter().cloned());
+
+        Ok(Guard { locks: self, vols })
+    }
+
+    // Wait for all of `vols` to be released.
+    fn wait<'a>(
+        &'a self,
+        mut held: MutexGuard<'a, HashSet<String>>,
+        vols: &[String],
+    ) -> Result<MutexGuard<'a, HashSet<String>>, Error> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        while vols.iter().any(|vol| held.contains(vol)) {
+            held = match deadline {
+                None => self
+                    .released
+                    .wait(held)
+                    .unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::LockTimeoutError(
+                            vols.join(", "),
+                            self.timeout.unwrap_or_default(),
+                        ));
+                    }
+                    self.released
+                        .wait_timeout(held, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                },
+            };
+        }
+        Ok(held)
+    }
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        let mut held = self
+            .locks
+            .held
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for vol in &self.vols {
+            held.remove(vol);
+        }
+        self.locks.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_exclusive() {
+        let locks = Locks::new(Some(Duration::from_millis(50)));
+
+        let guard = locks.acquire(&["a", "b"]).unwrap();
+        match locks.acquire(&["b", "c"]) {
+            Err(Error::LockTimeoutError(vols, _)) => assert_eq!(vols, "b, c"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        // Unrelated volumes aren't held up, and nothing was left locked by
+        // the failed attempt
+        locks.acquire(&["c"]).unwrap();
+
+        drop(guard);
+        locks.acquire(&["a", "b", "c"]).unwrap();
+    }
+
+    #[test]
+    fn test_waits_for_release() {
+        let locks = Arc::new(Locks::new(Some(Duration::from_secs(5))));
+        let guard = locks.acquire(&["a"]).unwrap();
+
+        let (tx, rx) = channel();
+        let waiter = {
+            let locks = locks.clone();
+            thread::spawn(move || {
+                let _guard = locks.acquire(&["a"]).unwrap();
+                tx.send(()).unwrap();
+            })
+        };
+
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(guard);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+    }
+}
@@ -1,10 +1,24 @@
 extern crate clap;
+#[macro_use] extern crate log;
 extern crate pretty_env_logger;
 
+use std::io;
 use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgGroup};
 
+use zockervols::api::ErrorResponse;
+use zockervols::autosnap;
+use zockervols::backend::Cli;
+use zockervols::client::Client;
+use zockervols::config::Config;
+use zockervols::ctl;
+use zockervols::gc;
+use zockervols::mounts::{Mounts, Owner};
+use zockervols::pool::Pool;
+use zockervols::reconcile::Outcome;
 use zockervols::server::run_server;
 use zockervols::zfs::Zfs;
 
@@ -27,13 +41,169 @@ fn main() {
                 .help("Override the UNIX socket Zockervols is listening on")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("admin-socket")
+                .long("admin-socket")
+                .value_name("FILE")
+                .help("Override the UNIX socket the admin API is served on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state")
+                .long("state-dir")
+                .value_name("DIR")
+                .help("Directory where Zockervols keeps state across restarts")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read configuration from FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reconcile")
+                .long("reconcile")
+                .help("Reconcile the state of the ZFS root with the mount table, and exit"),
+        )
+        .arg(
+            Arg::with_name("gc")
+                .long("gc")
+                .help("Remove unused volumes according to the GC policy, and exit"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .requires("oneshot")
+                .help("Only report what --reconcile or --gc would do"),
+        )
+        .group(ArgGroup::with_name("oneshot").args(&["reconcile", "gc"]))
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .global(true)
+                .help("Print the output of subcommands as JSON"),
+        )
+        .arg(
+            Arg::with_name("direct")
+                .long("direct")
+                .global(true)
+                .help("Have subcommands operate on ZFS directly, instead of via the daemon"),
+        )
+        .subcommands(ctl::subcommands())
         .get_matches();
 
+    let socket = opts
+        .value_of("socket")
+        .unwrap_or("/run/docker/plugins/zockervols.sock");
+    let admin_socket = opts
+        .value_of("admin-socket")
+        .unwrap_or("/run/zockervols/admin.sock");
+
+    let config = Config::load(&PathBuf::from(
+        opts.value_of("config")
+            .unwrap_or("/etc/zockervols/config.json"),
+    ))
+    .expect("Failed to load config");
+
+    let table = PathBuf::from(opts.value_of("state").unwrap_or("/var/lib/zockervols"))
+        .join("mounts.json");
+
+    let zfs = || {
+        let mounts = Mounts::load(table.clone()).expect("Failed to load mount table");
+
+        Zfs::new(
+            PathBuf::from(opts.value_of("root").unwrap_or("tank/zocker")),
+            mounts,
+            Arc::new(Cli::new(
+                config.exec.timeout.and_then(|timeout| timeout.to_std().ok()),
+            )),
+        )
+        .lock_timeout(
+            config
+                .exec
+                .lock_timeout
+                .and_then(|timeout| timeout.to_std().ok()),
+        )
+        .exclusive_wait(
+            config
+                .exec
+                .exclusive_wait
+                .and_then(|wait| wait.to_std().ok()),
+        )
+        .with_clones(config.remove.with_clones)
+        .option_policy(config.options.clone())
+        .profiles(config.profiles.clone())
+    };
+
+    if let (cmd, Some(args)) = opts.subcommand() {
+        let res = if args.is_present("direct") {
+            // The daemon keeps the mount table in memory, and would overwrite
+            // whatever we change
+            let _owner = match Owner::try_acquire(&table) {
+                Ok(Some(owner)) => owner,
+                Ok(None) => {
+                    eprintln!("The daemon is running, leave out --direct");
+                    process::exit(1);
+                },
+                Err(e) => {
+                    eprintln!("{}", ErrorResponse::from(e).err);
+                    process::exit(1);
+                },
+            };
+            ctl::run(&zfs(), cmd, args, &mut io::stdout())
+        } else {
+            let client = Client::new(PathBuf::from(socket), PathBuf::from(admin_socket));
+            ctl::run(&client, cmd, args, &mut io::stdout())
+        };
+        if let Err(e) = res {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Wait for subcommands run with --direct to finish
+    let _owner = Owner::acquire(&table).expect("Failed to take over mount table");
+    let zfs = zfs();
+
+    let dry_run = opts.is_present("dry-run");
+
+    if opts.is_present("gc") {
+        match zfs.gc(&config.gc, dry_run) {
+            Ok(collected) => gc::log_collected(&collected),
+            Err(e) => error!("GC: {:?}", e),
+        }
+        return;
+    }
+
+    match zfs.reconcile(dry_run) {
+        Ok(findings) => {
+            for finding in findings {
+                match finding.outcome {
+                    Outcome::Repaired => info!("Reconcile: repaired {}", finding.discrepancy),
+                    Outcome::DryRun => info!("Reconcile: found {}", finding.discrepancy),
+                    Outcome::Failed(e) => {
+                        error!("Reconcile: failed to repair {}: {}", finding.discrepancy, e)
+                    },
+                }
+            }
+        },
+        Err(e) => error!("Reconcile: {:?}", e),
+    }
+
+    if opts.is_present("reconcile") {
+        return;
+    }
+
+    gc::spawn(zfs.clone(), config.gc);
+    autosnap::spawn(zfs.clone(), config.auto_snapshot);
+
     run_server(
-        opts.value_of("socket")
-            .unwrap_or("/run/docker/plugins/zockervols.sock"),
-        Zfs::new(PathBuf::from(
-            opts.value_of("root").unwrap_or("tank/zocker"),
-        )),
+        socket,
+        admin_socket,
+        zfs,
+        Pool::new(config.exec.workers, config.exec.queue),
     )
 }
@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::zfs::Error;
+
+pub type Table = HashMap<String, HashSet<String>>;
+
+/// The set of container IDs holding each volume, keyed by volume name.
+///
+/// Every change is written through to a JSON state file, so that a restart of
+/// the daemon doesn't forget which volumes are in use. Changes only take
+/// effect once they have been written.
+#[derive(Debug)]
+pub struct Mounts {
+    path: Option<PathBuf>,
+    table: Mutex<Table>,
+}
+
+impl Mounts {
+    /// A mount table which is not persisted. Only useful for testing.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            table: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load the mount table from `path`, starting out empty if the file
+    /// doesn't exist yet.
+    pub fn load(path: PathBuf) -> Result<Self, Error> {
+        let table = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| Error::MountTableError(path.display().to_string(), e.to_string()))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            table: Mutex::new(table),
+        })
+    }
+
+    /// The containers currently holding `vol`.
+    pub fn owners(&self, vol: &str) -> Result<HashSet<String>, Error> {
+        self.lock(vol)
+            .map(|table| table.get(vol).cloned().unwrap_or_default())
+    }
+
+    /// All volumes with at least one owner.
+    pub fn all(&self) -> Result<Table, Error> {
+        self.lock("*").map(|table| table.clone())
+    }
+
+    /// Record `caller` as an owner of `vol`, returning whether it is the
+    /// first one.
+    pub fn insert(&self, vol: &str, caller: &str) -> Result<bool, Error> {
+        let mut table = self.lock(vol)?;
+        let mut updated = table.clone();
+        let owners = updated.entry(vol.to_string()).or_default();
+        let first = owners.is_empty();
+        owners.insert(caller.to_string());
+        self.persist(&updated)?;
+        *table = updated;
+        Ok(first)
+    }
+
+    /// Drop `caller` from the owners of `vol`, returning the remaining ones.
+    pub fn remove(&self, vol: &str, caller: &str) -> Result<HashSet<String>, Error> {
+        let mut table = self.lock(vol)?;
+        let mut updated = table.clone();
+        let remaining = match updated.get_mut(vol) {
+            None => HashSet::new(),
+            Some(owners) => {
+                owners.remove(caller);
+                owners.clone()
+            },
+        };
+        if remaining.is_empty() {
+            updated.remove(vol);
+        }
+        self.persist(&updated)?;
+        *table = updated;
+        Ok(remaining)
+    }
+
+    /// Forget about `vol` entirely, regardless of its owners.
+    pub fn forget(&self, vol: &str) -> Result<(), Error> {
+        let mut table = self.lock(vol)?;
+        if table.contains_key(vol) {
+            let mut updated = table.clone();
+            updated.remove(vol);
+            self.persist(&updated)?;
+            *table = updated;
+        }
+        Ok(())
+    }
+
+    fn lock(&self, vol: &str) -> Result<MutexGuard<Table>, Error> {
+        self.table
+            .lock()
+            .map_err(|e| Error::MountsLockError(vol.to_string(), e.to_string()))
+    }
+
+    fn persist(&self, table: &Table) -> Result<(), Error> {
+        match self.path {
+            None => Ok(()),
+            Some(ref path) => write_atomically(path, table).map_err(|e| {
+                Error::MountTableError(path.display().to_string(), e.to_string())
+            }),
+        }
+    }
+}
+
+/// Exclusive ownership of a mount table, held by the process changing it:
+/// the daemon, or a subcommand run with `--direct`. Released when dropped.
+#[derive(Debug)]
+pub struct Owner {
+    _file: fs::File,
+}
+
+impl Owner {
+    /// Take ownership of the mount table at `path`, waiting for whoever
+    /// holds it to let go.
+    pub fn acquire(path: &Path) -> Result<Self, Error> {
+        Self::flock(path, libc::LOCK_EX).map_err(|e| lock_error(path, e))
+    }
+
+    /// Take ownership of the mount table at `path`, unless another process
+    /// holds it.
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>, Error> {
+        match Self::flock(path, libc::LOCK_EX | libc::LOCK_NB) {
+            Ok(owner) => Ok(Some(owner)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(lock_error(path, e)),
+        }
+    }
+
+    fn flock(path: &Path, op: libc::c_int) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(path.with_extension("lock"))?;
+        if unsafe { libc::flock(file.as_raw_fd(), op) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+fn lock_error(path: &Path, e: io::Error) -> Error {
+    Error::MountTableError(path.with_extension("lock").display().to_string(), e.to_string())
+}
+
+// Write to a temporary file first, so we never leave a truncated state file
+// behind if we crash halfway through. Both the file and the rename are synced
+// to disk before returning, lest a power loss take back the change.
+fn write_atomically(path: &Path, table: &Table) -> io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(dir)?;
+    let tmp = path.with_extension("tmp");
+    let json = serde_json::to_vec(table).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(&json)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mounts_survive_reload() {
+        let path = std::env::temp_dir()
+            .join(format!("zockervols-mounts-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mounts = Mounts::load(path.clone()).unwrap();
+        assert!(mounts.insert("cache", "c1").unwrap());
+        assert!(!mounts.insert("cache", "c2").unwrap());
+        assert!(mounts.insert("other", "c3").unwrap());
+        assert_eq!(mounts.remove("other", "c3").unwrap(), HashSet::new());
+
+        let reloaded = Mounts::load(path.clone()).unwrap();
+        assert_eq!(
+            reloaded.owners("cache").unwrap(),
+            vec!["c1".to_string(), "c2".to_string()].into_iter().collect()
+        );
+        assert!(reloaded.owners("other").unwrap().is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_single_owner() {
+        let path = std::env::temp_dir()
+            .join(format!("zockervols-owner-{}.json", std::process::id()));
+
+        let owner = Owner::acquire(&path).unwrap();
+        assert!(Owner::try_acquire(&path).unwrap().is_none());
+        drop(owner);
+        assert!(Owner::try_acquire(&path).unwrap().is_some());
+
+        fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_changes_nothing() {
+        // The state file can't be written where a directory is in the way
+        let dir = std::env::temp_dir().join(format!("zockervols-mounts-{}.d", std::process::id()));
+        let path = dir.join("mounts.json");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mounts = Mounts::load(path.clone()).unwrap();
+        assert!(mounts.insert("cache", "c1").unwrap());
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(path.with_extension("tmp")).unwrap();
+
+        assert!(mounts.insert("cache", "c2").is_err());
+        assert!(mounts.remove("cache", "c1").is_err());
+        assert!(mounts.forget("cache").is_err());
+        assert_eq!(
+            mounts.owners("cache").unwrap(),
+            vec!["c1".to_string()].into_iter().collect()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::config::{de_bytes, Pattern};
+use crate::zfs::{parse_refquota, sanitize_vol, Error};
+
+/// Which options may be given when creating volumes.
+///
+/// The first rule matching the volume's name decides, and only the options it
+/// allows may be given. Volumes no rule matches may be created with any
+/// options. Options a clone inherits from its origin are checked as if they
+/// had been given, unless they were left at their defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Rule {
+    pub pattern: Pattern,
+    /// The options which may be given, by name.
+    #[serde(default)]
+    pub allow: HashMap<String, Allowed>,
+}
+
+/// The values an option may be given.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Allowed {
+    /// Any value if empty.
+    pub values: Vec<String>,
+    /// For sizes like `refquota`, the largest one permitted.
+    #[serde(deserialize_with = "de_bytes")]
+    pub max: Option<u64>,
+}
+
+impl Policy {
+    /// Check the options given for volume `name`, failing on the first one
+    /// which is not allowed.
+    pub fn check(&self, name: &str, opts: &HashMap<String, String>) -> Result<(), Error> {
+        let vol = sanitize_vol(name);
+        let rule = match self.rules.iter().find(|rule| rule.pattern.0.is_match(&vol)) {
+            Some(rule) => rule,
+            None => return Ok(()),
+        };
+
+        for (opt, value) in opts.iter().sorted() {
+            let allowed = rule
+                .allow
+                .get(opt)
+                .map_or(false, |allowed| allowed.permits(value));
+            if !allowed {
+                return Err(Error::OptionNotAllowedError(
+                    name.to_string(),
+                    opt.clone(),
+                    value.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Allowed {
+    fn permits(&self, value: &str) -> bool {
+        let listed = self.values.is_empty() || self.values.iter().any(|v| v == value);
+        // `none` or 0, as in no quota, is larger than any maximum
+        let in_range = self
+            .max
+            .map_or(true, |max| parse_refquota(value).map_or(false, |n| n > 0 && n <= max));
+        listed && in_range
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::ErrorResponse;
+
+    fn opts(opts: &[(&str, &str)]) -> HashMap<String, String> {
+        opts.iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let policy: Policy = serde_json::from_str(
+            r#"{
+                "rules": [
+                    {
+                        "pattern": "^img_",
+                        "allow": {
+                            "exec": {},
+                            "setuid": { "values": ["on", "off"] },
+                            "refquota": { "max": "20GiB" }
+                        }
+                    },
+                    {
+                        "pattern": ".",
+                        "allow": {
+                            "from": {},
+                            "exec": { "values": ["off"] },
+                            "refquota": { "max": "1GiB" }
+                        }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let check = |name, given| {
+            policy
+                .check(name, &opts(given))
+                .map_err(|e| ErrorResponse::from(e).err)
+        };
+
+        assert!(check("img_cache", &[("exec", "on"), ("refquota", "15GiB")]).is_ok());
+        assert!(check("build", &[("from", "img_cache"), ("exec", "off")]).is_ok());
+        assert_eq!(
+            check("build", &[("exec", "off"), ("setuid", "on")]),
+            Err("Option setuid=on is not allowed for volume build".to_string())
+        );
+        assert_eq!(
+            check("build", &[("exec", "on")]),
+            Err("Option exec=on is not allowed for volume build".to_string())
+        );
+        assert!(check("build", &[("refquota", "1GiB")]).is_ok());
+        assert!(check("build", &[("refquota", "2GiB")]).is_err());
+        assert!(check("build", &[("refquota", "lots")]).is_err());
+        assert!(check("build", &[("refquota", "0")]).is_err());
+
+        // No rule, no restrictions
+        assert!(Policy::default().check("build", &opts(&[("setuid", "on")])).is_ok());
+    }
+}
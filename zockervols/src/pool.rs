@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::zfs::Error;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads to run blocking work on, with a bounded queue.
+///
+/// Once the queue is full, further work is refused instead of piling up. Jobs
+/// waiting on other jobs, like for a volume lock, do so in `blocking`, so that
+/// they don't keep the workers from unrelated ones.
+pub struct Pool {
+    tx: Mutex<SyncSender<Job>>,
+}
+
+struct Shared {
+    rx: Mutex<Receiver<Job>>,
+    /// Workers to let go, once the jobs which were waiting in `blocking`
+    /// are running again.
+    surplus: AtomicUsize,
+    spawned: AtomicUsize,
+}
+
+thread_local! {
+    static WORKER: RefCell<Option<Arc<Shared>>> = RefCell::new(None);
+}
+
+impl Pool {
+    pub fn new(workers: usize, queue: usize) -> Self {
+        let (tx, rx) = mpsc::sync_channel::<Job>(queue);
+        let shared = Arc::new(Shared {
+            rx: Mutex::new(rx),
+            surplus: AtomicUsize::new(0),
+            spawned: AtomicUsize::new(0),
+        });
+
+        for _ in 0..workers.max(1) {
+            spawn_worker(shared.clone());
+        }
+
+        Self { tx: Mutex::new(tx) }
+    }
+
+    pub fn execute<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.tx
+            .lock()
+            .unwrap()
+            .try_send(Box::new(f))
+            .map_err(|e| match e {
+                TrySendError::Full(_) => Error::WorkersBusyError,
+                TrySendError::Disconnected(_) => Error::WorkersBusyError,
+            })
+    }
+}
+
+/// Run `f`, which may wait on other jobs for a while. If called from a
+/// worker, another one takes its place until `f` returns.
+pub fn blocking<T, F: FnOnce() -> T>(f: F) -> T {
+    // Nested calls already have a replacement
+    let _resume = match WORKER.with(|worker| worker.borrow_mut().take()) {
+        None => None,
+        Some(shared) => {
+            spawn_worker(shared.clone());
+            Some(Resume(shared))
+        },
+    };
+    f()
+}
+
+// Back to being a regular worker, one too many for now
+struct Resume(Arc<Shared>);
+
+impl Drop for Resume {
+    fn drop(&mut self) {
+        self.0.surplus.fetch_add(1, Ordering::SeqCst);
+        WORKER.with(|worker| *worker.borrow_mut() = Some(self.0.clone()));
+    }
+}
+
+impl Shared {
+    fn retire(&self) -> bool {
+        let mut n = self.surplus.load(Ordering::SeqCst);
+        while n > 0 {
+            match self
+                .surplus
+                .compare_exchange(n, n - 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return true,
+                Err(actual) => n = actual,
+            }
+        }
+        false
+    }
+}
+
+fn spawn_worker(shared: Arc<Shared>) {
+    let i = shared.spawned.fetch_add(1, Ordering::SeqCst);
+    thread::Builder::new()
+        .name(format!("worker-{}", i))
+        .spawn(move || {
+            WORKER.with(|worker| *worker.borrow_mut() = Some(shared.clone()));
+            loop {
+                // Release the lock before running the job
+                let next = shared.rx.lock().unwrap().recv();
+                let job = match next {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                // Don't let a panicking job take the worker down with it
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    error!("Worker {}: job panicked", i);
+                }
+                if shared.retire() {
+                    break;
+                }
+            }
+        })
+        .expect("Failed to spawn worker thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bounded() {
+        let pool = Pool::new(1, 1);
+        let (block_tx, block_rx) = channel::<()>();
+        let (done_tx, done_rx) = channel();
+
+        // Occupy the worker, and wait until it's busy
+        let (started_tx, started_rx) = channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            block_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        // One more fits into the queue
+        let done = done_tx.clone();
+        pool.execute(move || done.send(1).unwrap()).unwrap();
+        match pool.execute(move || done_tx.send(2).unwrap()) {
+            Err(Error::WorkersBusyError) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        block_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_blocking() {
+        let pool = Pool::new(1, 2);
+        let (block_tx, block_rx) = channel::<()>();
+        let (done_tx, done_rx) = channel();
+
+        let done = done_tx.clone();
+        pool.execute(move || {
+            blocking(|| block_rx.recv().unwrap());
+            done.send(1).unwrap();
+        })
+        .unwrap();
+        // Not held up by the waiting job
+        pool.execute(move || done_tx.send(2).unwrap()).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), 2);
+
+        block_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_survives_panics() {
+        let pool = Pool::new(1, 2);
+        let (tx, rx) = channel();
+        pool.execute(|| panic!("boom")).unwrap();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
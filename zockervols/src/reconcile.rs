@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::ErrorResponse;
+use crate::backend::Destroy;
+use crate::zfs::{
+    clone_name, get_mountpoint_of, remove_mountpoint_of, sanitize_vol, Cmd, Error, Zfs,
+};
+
+/// An inconsistency between the datasets under the root, the directories
+/// under the root's mountpoint, and the mount table.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Discrepancy {
+    /// A dataset left behind by a create which didn't run to completion.
+    IncompleteVolume { vol: String },
+    /// A dataset which is still mounted, although nobody is using it.
+    StaleMountpoint { vol: String, mountpoint: PathBuf },
+    /// A dataset which is in use according to the mount table, but isn't
+    /// mounted.
+    NotMounted { vol: String, owners: Vec<String> },
+    /// The mount directory of an unmounted dataset.
+    LeftoverDirectory { vol: String, path: PathBuf },
+    /// A directory which doesn't belong to any dataset.
+    OrphanedDirectory { path: PathBuf },
+    /// Owners recorded for a volume which doesn't exist.
+    StaleMountEntry { vol: String, owners: Vec<String> },
+    /// An ephemeral volume which has been unmounted for good, but not
+    /// destroyed.
+    OrphanedEphemeral { vol: String },
+    /// The private clone of a per-mount-clone volume, left behind by a
+    /// container which no longer has it mounted.
+    OrphanedClone { vol: String, id: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Repaired,
+    Failed(String),
+    DryRun,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Finding {
+    pub discrepancy: Discrepancy,
+    pub outcome: Outcome,
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discrepancy::IncompleteVolume { vol } => write!(f, "incomplete volume {}", vol),
+            Discrepancy::StaleMountpoint { vol, mountpoint } => write!(
+                f,
+                "volume {} is mounted at {}, but not in use",
+                vol,
+                mountpoint.display()
+            ),
+            Discrepancy::NotMounted { vol, owners } => write!(
+                f,
+                "volume {} is in use by {}, but not mounted",
+                vol,
+                owners.join(", ")
+            ),
+            Discrepancy::LeftoverDirectory { vol, path } => write!(
+                f,
+                "leftover mount directory {} of volume {}",
+                path.display(),
+                vol
+            ),
+            Discrepancy::OrphanedDirectory { path } => {
+                write!(f, "orphaned directory {}", path.display())
+            },
+            Discrepancy::StaleMountEntry { vol, owners } => write!(
+                f,
+                "mount table entry for non-existent volume {} (owners: {})",
+                vol,
+                owners.join(", ")
+            ),
+            Discrepancy::OrphanedEphemeral { vol } => {
+                write!(f, "ephemeral volume {} is no longer in use", vol)
+            },
+            Discrepancy::OrphanedClone { vol, id } => {
+                write!(f, "clone of volume {} for {} is no longer in use", vol, id)
+            },
+        }
+    }
+}
+
+/// What `zfs list` tells us about a direct child of the root dataset.
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub vol: String,
+    pub mountpoint: Option<PathBuf>,
+    pub incomplete: bool,
+    pub per_mount_clone: bool,
+    pub ephemeral: bool,
+    pub ever_mounted: bool,
+    /// The sanitized container IDs the volume has private clones for.
+    pub clones: Vec<String>,
+}
+
+impl Zfs {
+    /// Walk the datasets under the root and the root's mountpoint directory,
+    /// and repair any discrepancies found. If `dry_run` is `true`, only report
+    /// them.
+    pub fn reconcile(&self, dry_run: bool) -> Result<Vec<Finding>, Error> {
+        let root_mountpoint = get_mountpoint_of(&*self.backend, &self.root.to_string_lossy())?;
+        let entries = self.survey()?;
+        let dirs = fs::read_dir(&root_mountpoint)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let mounts = self.mounts.all()?;
+
+        let findings = classify(&root_mountpoint, &entries, &dirs, &mounts)
+            .into_iter()
+            .map(|discrepancy| {
+                let outcome = if dry_run {
+                    Outcome::DryRun
+                } else {
+                    match self.repair(&discrepancy) {
+                        Ok(()) => Outcome::Repaired,
+                        Err(e) => Outcome::Failed(ErrorResponse::from(e).err),
+                    }
+                };
+                Finding {
+                    discrepancy,
+                    outcome,
+                }
+            })
+            .collect();
+
+        Ok(findings)
+    }
+
+    fn repair(&self, discrepancy: &Discrepancy) -> Result<(), Error> {
+        let _lock = match discrepancy {
+            Discrepancy::IncompleteVolume { vol }
+            | Discrepancy::StaleMountpoint { vol, .. }
+            | Discrepancy::NotMounted { vol, .. }
+            | Discrepancy::OrphanedEphemeral { vol }
+            | Discrepancy::OrphanedClone { vol, .. } => Some(self.lock(&[vol])?),
+            _ => None,
+        };
+        match discrepancy {
+            Discrepancy::IncompleteVolume { vol } => {
+                Cmd::destroy(vol).run(&self.root, &*self.backend)
+            },
+            Discrepancy::StaleMountpoint { vol, .. } => {
+                // Unless it has been mounted in the meantime
+                let owners = self.owners_of(vol)?;
+                if !owners.is_empty() {
+                    return Err(Error::VolInUseError(vol.to_string(), owners));
+                }
+                remove_mountpoint_of(&*self.backend, &self.dataset(vol))
+            },
+            Discrepancy::NotMounted { vol, .. } => Cmd::mount(vol).run(&self.root, &*self.backend),
+            Discrepancy::LeftoverDirectory { path, .. }
+            | Discrepancy::OrphanedDirectory { path } => {
+                fs::remove_dir(path).map_err(|e| e.into())
+            },
+            Discrepancy::StaleMountEntry { vol, .. } => self.mounts.forget(vol),
+            Discrepancy::OrphanedEphemeral { vol } => {
+                let owners = self.owners_of(vol)?;
+                if !owners.is_empty() {
+                    return Err(Error::VolInUseError(vol.to_string(), owners));
+                }
+                self.destroy_volume(vol)
+            },
+            Discrepancy::OrphanedClone { vol, id } => {
+                let owners = self.owners_of(vol)?;
+                if owners.iter().any(|owner| sanitize_vol(owner) == *id) {
+                    return Err(Error::VolInUseError(vol.to_string(), owners));
+                }
+                let clone = clone_name(&self.root, vol, id);
+                if get_mountpoint_of(&*self.backend, &clone).is_ok() {
+                    remove_mountpoint_of(&*self.backend, &clone)?;
+                }
+                self.backend.destroy(&clone, Destroy::Single)
+            },
+        }
+    }
+}
+
+pub(crate) fn classify(
+    root_mountpoint: &Path,
+    entries: &[Entry],
+    dirs: &[String],
+    mounts: &HashMap<String, HashSet<String>>,
+) -> Vec<Discrepancy> {
+    // The mount table is keyed by the names Docker knows, datasets and
+    // directories by their sanitized form.
+    let owners: HashMap<String, (&String, Vec<String>)> = mounts
+        .iter()
+        .filter(|(_, by)| !by.is_empty())
+        .map(|(name, by)| {
+            let mut by = by.iter().cloned().collect::<Vec<_>>();
+            by.sort();
+            (sanitize_vol(name), (name, by))
+        })
+        .collect();
+
+    let mut found = Vec::new();
+
+    for entry in entries {
+        let in_use = owners.get(&entry.vol);
+        let dir = root_mountpoint.join(&entry.vol);
+        let has_dir = dirs.contains(&entry.vol);
+
+        if entry.incomplete && in_use.is_none() {
+            found.push(Discrepancy::IncompleteVolume {
+                vol: entry.vol.clone(),
+            });
+            continue;
+        }
+
+        // Volumes only become ephemeral once mounted, lest we destroy one
+        // which was just created
+        if entry.ephemeral && entry.ever_mounted && in_use.is_none() {
+            found.push(Discrepancy::OrphanedEphemeral {
+                vol: entry.vol.clone(),
+            });
+            continue;
+        }
+
+        // Containers don't get to unmount them if the daemon was down, and
+        // they pin the volume's snapshots
+        for id in &entry.clones {
+            let owned = in_use.map_or(false, |(_, by)| by.iter().any(|o| sanitize_vol(o) == *id));
+            if !owned {
+                found.push(Discrepancy::OrphanedClone {
+                    vol: entry.vol.clone(),
+                    id: id.clone(),
+                });
+            }
+        }
+
+        match (&entry.mountpoint, in_use) {
+            (Some(mountpoint), None) => found.push(Discrepancy::StaleMountpoint {
+                vol: entry.vol.clone(),
+                mountpoint: mountpoint.clone(),
+            }),
+            // Only the private clones of such a volume are ever mounted
+            (None, Some(_)) if entry.per_mount_clone => {},
+            (None, Some((_, by))) => found.push(Discrepancy::NotMounted {
+                vol: entry.vol.clone(),
+                owners: by.clone(),
+            }),
+            (None, None) if has_dir => found.push(Discrepancy::LeftoverDirectory {
+                vol: entry.vol.clone(),
+                path: dir,
+            }),
+            _ => {},
+        }
+    }
+
+    let known = entries.iter().map(|e| &e.vol).collect::<HashSet<_>>();
+    for dir in dirs {
+        if !known.contains(dir) {
+            found.push(Discrepancy::OrphanedDirectory {
+                path: root_mountpoint.join(dir),
+            })
+        }
+    }
+    for (vol, (name, by)) in owners {
+        if !known.contains(&vol) {
+            found.push(Discrepancy::StaleMountEntry {
+                vol: name.clone(),
+                owners: by,
+            })
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(vol: &str, mountpoint: Option<&str>, incomplete: bool) -> Entry {
+        Entry {
+            vol: vol.to_string(),
+            mountpoint: mountpoint.map(PathBuf::from),
+            incomplete,
+            per_mount_clone: false,
+            ephemeral: false,
+            ever_mounted: false,
+            clones: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_classify_consistent() {
+        let entries = vec![
+            entry("idle", None, false),
+            entry("busy", Some("/mnt/zocker/busy"), false),
+        ];
+        let dirs = vec!["busy".to_string()];
+        let mounts = vec![("busy".to_string(), vec!["c1".to_string()].into_iter().collect())]
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            classify(Path::new("/mnt/zocker"), &entries, &dirs, &mounts),
+            vec![]
+        )
+    }
+
+    #[test]
+    fn test_classify_discrepancies() {
+        let entries = vec![
+            entry("half", Some("/mnt/zocker/half"), true),
+            entry("crashed", Some("/mnt/zocker/crashed"), false),
+            entry("leftover", None, false),
+            entry("lost", None, false),
+        ];
+        let dirs = vec![
+            "half".to_string(),
+            "crashed".to_string(),
+            "leftover".to_string(),
+            "orphan".to_string(),
+        ];
+        let mounts = vec![
+            ("lost".to_string(), vec!["c1".to_string()].into_iter().collect()),
+            ("gone:1".to_string(), vec!["c2".to_string()].into_iter().collect()),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut found = classify(Path::new("/mnt/zocker"), &entries, &dirs, &mounts);
+        found.sort_by_key(|d| d.to_string());
+
+        assert_eq!(
+            found,
+            vec![
+                Discrepancy::IncompleteVolume {
+                    vol: "half".to_string()
+                },
+                Discrepancy::LeftoverDirectory {
+                    vol: "leftover".to_string(),
+                    path: PathBuf::from("/mnt/zocker/leftover")
+                },
+                Discrepancy::StaleMountEntry {
+                    vol: "gone:1".to_string(),
+                    owners: vec!["c2".to_string()]
+                },
+                Discrepancy::OrphanedDirectory {
+                    path: PathBuf::from("/mnt/zocker/orphan")
+                },
+                Discrepancy::StaleMountpoint {
+                    vol: "crashed".to_string(),
+                    mountpoint: PathBuf::from("/mnt/zocker/crashed")
+                },
+                Discrepancy::NotMounted {
+                    vol: "lost".to_string(),
+                    owners: vec!["c1".to_string()]
+                },
+            ]
+        )
+    }
+}
@@ -1,97 +1,113 @@
+use std::fs;
+use std::io;
 use std::marker::Sync;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net;
 use std::path::Path;
+use std::process;
+use std::sync::Arc;
 
 use listenfd::ListenFd;
 use serde::de::DeserializeOwned;
 use tokio::net::UnixListener;
+use tokio::prelude::{future, Future};
 use tokio::reactor::Handle;
+use tokio::sync::oneshot;
 use warp::Filter;
 
+use crate::admin::*;
 use crate::api::*;
+use crate::pool::Pool;
 
-pub fn run_server<P, H>(socket: P, handler: H)
+/// Serve the Docker volume plugin API on `socket`, and the admin API on
+/// `admin_socket`.
+///
+/// If started via systemd socket activation, the sockets passed are told apart
+/// by their paths. Those not passed are bound here.
+///
+/// Requests which may run ZFS commands are handled on `pool`, so that slow
+/// commands don't hold up unrelated requests.
+pub fn run_server<P, H>(socket: P, admin_socket: P, handler: H, pool: Pool)
 where
     P: AsRef<Path> + ToString,
-    H: VolumePlugin + Clone + Sync + Send + 'static,
+    H: VolumePlugin + AdminApi + Clone + Sync + Send + 'static,
 {
-    let handler = warp::any().map(move || handler.clone());
+    let worker = Worker {
+        handler,
+        pool: Arc::new(pool),
+    };
+    let admin_worker = worker.clone();
+    let handler = warp::any().map(move || worker.clone());
 
     let activate = warp::path(ACTIVATE)
         .and(handler.clone())
-        .map(|h: H| warp::reply::json(&h.activate()));
+        .map(|w: Worker<H>| warp::reply::json(&w.handler.activate()));
 
     let create = warp::path(CREATE)
         .and(json_request())
         .and(handler.clone())
-        .and_then(|rq: CreateRequest, h: H| {
-            h.create(rq)
+        .and_then(|rq: CreateRequest, w: Worker<H>| {
+            w.run(move |h| h.create(rq))
                 .map(|k| warp::reply::json(&k))
-                .map_err(warp::reject::custom)
         })
         .recover(error_response);
 
     let get = warp::path(GET)
         .and(json_request())
         .and(handler.clone())
-        .and_then(|rq: GetRequest, h: H| {
-            h.get(rq)
+        .and_then(|rq: GetRequest, w: Worker<H>| {
+            w.run(move |h| h.get(rq))
                 .map(|k| warp::reply::json(&k))
-                .map_err(warp::reject::custom)
         })
         .recover(error_response);
 
     let list = warp::path(LIST)
         .and(handler.clone())
-        .and_then(|h: H| {
-            h.list()
+        .and_then(|w: Worker<H>| {
+            w.run(move |h| h.list())
                 .map(|k| warp::reply::json(&k))
-                .map_err(warp::reject::custom)
         })
         .recover(error_response);
 
     let remove = warp::path(REMOVE)
         .and(json_request())
         .and(handler.clone())
-        .and_then(|rq: RemoveRequest, h: H| {
-            h.remove(rq)
+        .and_then(|rq: RemoveRequest, w: Worker<H>| {
+            w.run(move |h| h.remove(rq))
                 .map(|k| warp::reply::json(&k))
-                .map_err(warp::reject::custom)
         })
         .recover(error_response);
 
     let path = warp::path(PATH)
         .and(json_request())
         .and(handler.clone())
-        .and_then(|rq: PathRequest, h: H| {
-            h.path(rq)
+        .and_then(|rq: PathRequest, w: Worker<H>| {
+            w.run(move |h| h.path(rq))
                 .map(|k| warp::reply::json(&k))
-                .map_err(warp::reject::custom)
         })
         .recover(error_response);
 
     let mount = warp::path(MOUNT)
         .and(json_request())
         .and(handler.clone())
-        .and_then(|rq: MountRequest, h: H| {
-            h.mount(rq)
+        .and_then(|rq: MountRequest, w: Worker<H>| {
+            w.run(move |h| h.mount(rq))
                 .map(|k| warp::reply::json(&k))
-                .map_err(warp::reject::custom)
         })
         .recover(error_response);
 
     let unmount = warp::path(UNMOUNT)
         .and(json_request())
         .and(handler.clone())
-        .and_then(|rq: UnmountRequest, h: H| {
-            h.unmount(rq)
+        .and_then(|rq: UnmountRequest, w: Worker<H>| {
+            w.run(move |h| h.unmount(rq))
                 .map(|k| warp::reply::json(&k))
-                .map_err(warp::reject::custom)
         })
         .recover(error_response);
 
     let capabilities = warp::path(CAPABILITIES)
         .and(handler)
-        .map(|h: H| warp::reply::json(&h.capabilities()));
+        .map(|w: Worker<H>| warp::reply::json(&w.handler.capabilities()));
 
     let routes = warp::post2().and(
         activate
@@ -105,26 +121,226 @@ where
             .or(capabilities),
     );
 
-    let server = warp::serve(routes);
+    let admin_handler = warp::any().map(move || admin_worker.clone());
+
+    let snapshot = warp::path(SNAPSHOT)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: SnapshotRequest, w: Worker<H>| {
+            w.run(move |h| h.snapshot(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let rollback = warp::path(ROLLBACK)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: RollbackRequest, w: Worker<H>| {
+            w.run(move |h| h.rollback(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let snapshots = warp::path(SNAPSHOTS)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: SnapshotsRequest, w: Worker<H>| {
+            w.run(move |h| h.snapshots(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let destroy_snapshot = warp::path(DESTROY_SNAPSHOT)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: DestroySnapshotRequest, w: Worker<H>| {
+            w.run(move |h| h.destroy_snapshot(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let set_quota = warp::path(SET_QUOTA)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: SetQuotaRequest, w: Worker<H>| {
+            w.run(move |h| h.set_quota(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let clones = warp::path(CLONES)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: ClonesRequest, w: Worker<H>| {
+            w.run(move |h| h.clones(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let force_unmount = warp::path(FORCE_UNMOUNT)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: ForceUnmountRequest, w: Worker<H>| {
+            w.run(move |h| h.force_unmount(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let reconcile = warp::path(RECONCILE)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: ReconcileRequest, w: Worker<H>| {
+            w.run(move |h| h.reconcile(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let commit = warp::path(COMMIT)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: CommitRequest, w: Worker<H>| {
+            w.run(move |h| h.commit(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let restore = warp::path(RESTORE)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: RestoreRequest, w: Worker<H>| {
+            w.run(move |h| h.restore(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let lineage = warp::path(LINEAGE)
+        .and(json_request())
+        .and(admin_handler.clone())
+        .and_then(|rq: LineageRequest, w: Worker<H>| {
+            w.run(move |h| h.lineage(rq))
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let mounts = warp::path(MOUNTS)
+        .and(admin_handler)
+        .and_then(|w: Worker<H>| {
+            w.run(move |h| h.mounts())
+                .map(|k| warp::reply::json(&k))
+        })
+        .recover(error_response);
+
+    let admin_routes = warp::post2().and(
+        snapshot
+            .or(rollback)
+            .or(snapshots)
+            .or(destroy_snapshot)
+            .or(set_quota)
+            .or(clones)
+            .or(force_unmount)
+            .or(reconcile)
+            .or(commit)
+            .or(restore)
+            .or(lineage)
+            .or(mounts),
+    );
 
     let mut fds = ListenFd::from_env();
-    if let Some(listener) = fds.take_unix_listener(0).unwrap() {
-        server.run_incoming(
-            UnixListener::from_std(listener, &Handle::default())
-                .unwrap()
-                .incoming(),
-        )
-    } else {
-        let err = format!(
-            "Can't bind to UNIX socket at {}",
-            socket.to_string().as_str()
-        );
-        // FIXME: set permissions to 700
-        let listener = UnixListener::bind(&socket).expect(&err);
-        server.run_incoming(listener.incoming())
+    let mut activated = (0..fds.len())
+        .filter_map(|idx| fds.take_unix_listener(idx).unwrap())
+        .collect::<Vec<_>>();
+    let listener = listen(&mut activated, &socket);
+    let admin_listener = listen(&mut activated, &admin_socket);
+
+    tokio::run(
+        warp::serve(routes)
+            .serve_incoming(listener.incoming())
+            .join(warp::serve(admin_routes).serve_incoming(admin_listener.incoming()))
+            .map(|_| ()),
+    )
+}
+
+/// A handler, and the pool to run its methods on.
+#[derive(Clone)]
+struct Worker<H> {
+    handler: H,
+    pool: Arc<Pool>,
+}
+
+impl<H: Send + 'static> Worker<H> {
+    fn run<T, F>(self, f: F) -> impl Future<Item = T, Error = warp::Rejection>
+    where
+        T: Send + 'static,
+        F: FnOnce(H) -> Result<T, ErrorResponse> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let handler = self.handler;
+        let queued = self.pool.execute(move || {
+            // The request may have gone away in the meantime
+            let _ = tx.send(f(handler));
+        });
+
+        future::result(queued.map_err(ErrorResponse::from))
+            .and_then(|()| {
+                rx.then(|res| match res {
+                    Ok(res) => res,
+                    Err(_) => Err(ErrorResponse::from("Worker failed to respond")),
+                })
+            })
+            .map_err(warp::reject::custom)
     }
 }
 
+fn listen<P>(activated: &mut Vec<net::UnixListener>, socket: &P) -> UnixListener
+where
+    P: AsRef<Path> + ToString,
+{
+    let path = |listener: &net::UnixListener| {
+        listener
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+    };
+    let listener = match activated
+        .iter()
+        .position(|listener| path(listener).as_deref() == Some(socket.as_ref()))
+    {
+        Some(idx) => activated.remove(idx),
+        None => {
+            let err = format!(
+                "Can't bind to UNIX socket at {}",
+                socket.to_string().as_str()
+            );
+            if let Some(dir) = socket.as_ref().parent() {
+                fs::create_dir_all(dir).expect(&err);
+            }
+            // Only root and the user the daemon runs as may use either API,
+            // as even the plugin API lets one create volumes with `exec=on`
+            bind_private(socket.as_ref(), 0o600).expect(&err)
+        },
+    };
+    UnixListener::from_std(listener, &Handle::default()).unwrap()
+}
+
+// Bind in a directory nobody else can enter, so that the socket is never
+// accessible with looser permissions than `mode`, and move it into place from
+// there.
+fn bind_private(socket: &Path, mode: u32) -> io::Result<net::UnixListener> {
+    let dir = socket.parent().unwrap_or_else(|| Path::new("."));
+    let private = dir.join(format!(".zockervols-{}", process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bind = || {
+        let tmp = private.join("sock");
+        let listener = net::UnixListener::bind(&tmp)?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(mode))?;
+        fs::rename(&tmp, socket)?;
+        Ok(listener)
+    };
+    let res = bind();
+    let _ = fs::remove_dir_all(&private);
+    res
+}
+
 fn json_request<T: DeserializeOwned + Send>(
 ) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Copy {
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
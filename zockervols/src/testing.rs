@@ -0,0 +1,61 @@
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::backend::{Memory, Properties, ZfsBackend};
+use crate::mounts::Mounts;
+use crate::zfs::Zfs;
+
+/// A `Zfs` on top of a simulated pool, with the root dataset `tank/zocker`
+/// mounted at a fresh temporary directory.
+pub struct Fixture {
+    pub zfs: Zfs,
+    pub mem: Arc<Memory>,
+    pub dir: PathBuf,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = env::temp_dir().join(format!(
+            "zockervols-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(dir.join("zocker")).unwrap();
+
+        let mem = Arc::new(Memory::default());
+        mem.create("tank", &props(&[("mountpoint", &dir.to_string_lossy())]))
+            .unwrap();
+        mem.create("tank/zocker", &Properties::new()).unwrap();
+
+        let zfs = Zfs::new(PathBuf::from("tank/zocker"), Mounts::in_memory(), mem.clone());
+
+        Self { zfs, mem, dir }
+    }
+}
+
+impl Deref for Fixture {
+    type Target = Zfs;
+
+    fn deref(&self) -> &Self::Target {
+        &self.zfs
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn props(kvs: &[(&str, &str)]) -> Properties {
+    kvs.iter()
+        .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+        .collect()
+}
@@ -0,0 +1,151 @@
+use crate::zfs::Error;
+
+type Undo<'a> = Box<dyn FnOnce() -> Result<(), Error> + 'a>;
+
+/// A sequence of steps which either all take effect, or none.
+///
+/// Every step may register an action undoing it. When a step fails, the undo
+/// actions are run in reverse order, and those which failed in turn are
+/// reported along with the step's error. Unless the transaction is committed,
+/// the same happens when it is dropped, so bailing out early at any point
+/// rolls back what has been done so far.
+pub(crate) struct Transaction<'a> {
+    vol: String,
+    undo: Vec<(&'static str, Undo<'a>)>,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new(vol: &str) -> Self {
+        Self {
+            vol: vol.to_string(),
+            undo: Vec::new(),
+        }
+    }
+
+    /// Run step `name`, registering `undo` if it succeeds.
+    pub fn step<T, F, U>(&mut self, name: &'static str, f: F, undo: U) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+        U: FnOnce() -> Result<(), Error> + 'a,
+    {
+        let res = self.run(name, f)?;
+        self.undo.push((name, Box::new(undo)));
+        Ok(res)
+    }
+
+    /// Run step `name`, which doesn't need to be undone.
+    pub fn run<T, F>(&mut self, name: &'static str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        f().map_err(|e| {
+            let left = self.rollback();
+            Error::TransactionError(self.vol.clone(), name, Box::new(e), left)
+        })
+    }
+
+    pub fn commit(mut self) {
+        self.undo.clear()
+    }
+
+    /// Undo the steps taken so far, returning those which could not be undone.
+    fn rollback(&mut self) -> Vec<(&'static str, Error)> {
+        let mut left = Vec::new();
+        while let Some((name, undo)) = self.undo.pop() {
+            match undo() {
+                Ok(()) => info!("{}: rolled back {}", self.vol, name),
+                Err(e) => {
+                    error!("{}: failed to roll back {}: {:?}", self.vol, name, e);
+                    left.push((name, e));
+                },
+            }
+        }
+        left
+    }
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        self.rollback();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::api::ErrorResponse;
+
+    #[test]
+    fn test_rollback_in_reverse() {
+        let undone = RefCell::new(Vec::new());
+        let res: Result<(), Error> = (|| {
+            let mut tx = Transaction::new("vol");
+            tx.step("one", || Ok(()), || {
+                undone.borrow_mut().push("one");
+                Ok(())
+            })?;
+            tx.step("two", || Ok(()), || {
+                undone.borrow_mut().push("two");
+                Ok(())
+            })?;
+            tx.run("three", || Err(Error::NoMountpointError("vol".to_string())))?;
+            tx.commit();
+            Ok(())
+        })();
+
+        match res {
+            Err(Error::TransactionError(vol, step, _, left)) => {
+                assert_eq!(vol, "vol");
+                assert_eq!(step, "three");
+                assert!(left.is_empty());
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert_eq!(*undone.borrow(), vec!["two", "one"]);
+    }
+
+    #[test]
+    fn test_rollback_incomplete() {
+        let undone = RefCell::new(Vec::new());
+        let res: Result<(), Error> = (|| {
+            let mut tx = Transaction::new("vol");
+            tx.step("one", || Ok(()), || {
+                undone.borrow_mut().push("one");
+                Ok(())
+            })?;
+            tx.step("two", || Ok(()), || {
+                Err(Error::MountTableError("mounts.json".to_string(), "read-only".to_string()))
+            })?;
+            tx.run("three", || Err(Error::NoMountpointError("vol".to_string())))?;
+            tx.commit();
+            Ok(())
+        })();
+
+        assert_eq!(
+            res.map_err(|e| ErrorResponse::from(e).err),
+            Err("Volume vol: three failed: No mountpoint for vol; \
+                 rollback incomplete, left behind: two (Mount table mounts.json: read-only)"
+                .to_string())
+        );
+        // The remaining steps are still undone
+        assert_eq!(*undone.borrow(), vec!["one"]);
+    }
+
+    #[test]
+    fn test_commit() {
+        let undone = RefCell::new(Vec::new());
+        {
+            let mut tx = Transaction::new("vol");
+            let undo = || {
+                undone.borrow_mut().push("one");
+                Ok(())
+            };
+            tx.step("one", || Ok(()), undo).unwrap();
+            tx.commit();
+        }
+        assert!(undone.borrow().is_empty());
+    }
+}
@@ -1,5 +1,4 @@
-use std::borrow::BorrowMut;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Display;
@@ -7,115 +6,208 @@ use std::fs;
 use std::io;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::thread;
+use std::time::{self, Instant, SystemTime};
 
 use byte_unit::Byte;
-use chashmap::CHashMap;
 use chrono::prelude::*;
 use chrono::serde::ts_seconds;
+use chrono::Duration;
 use itertools::Itertools;
 use regex::Regex;
 use serde::Deserialize;
-use users::{
-    get_effective_gid, get_effective_groupname, get_effective_uid, get_effective_username,
-};
 
 use crate::api::*;
-
-enum Cmd {
-    Create { vol: String, opts: VolumeOptions },
+use crate::autosnap;
+use crate::backend::{Destroy, Kind, Properties, ZfsBackend};
+use crate::config::{format_duration, parse_duration, Profiles, WithClones};
+use crate::locks::{Guard, Locks};
+use crate::mounts::{Mounts, Table};
+use crate::options;
+use crate::pool;
+use crate::reconcile::Entry;
+use crate::transaction::Transaction;
+
+/// User property marking a dataset whose creation hasn't completed yet.
+pub(crate) const PROP_INCOMPLETE: &str = "zockervols:incomplete";
+
+/// User property holding the `ttl=` option of a volume, in seconds.
+pub(crate) const PROP_TTL: &str = "zockervols:ttl";
+
+/// User properties recording the mount history of a volume. Times are in
+/// seconds since the epoch.
+pub(crate) const PROP_LAST_MOUNTED: &str = "zockervols:last-mounted";
+pub(crate) const PROP_LAST_UNMOUNTED: &str = "zockervols:last-unmounted";
+pub(crate) const PROP_MOUNT_COUNT: &str = "zockervols:mount-count";
+pub(crate) const PROP_LAST_CONTAINER: &str = "zockervols:last-container";
+
+/// User property marking a volume created with `per-mount-clone=on`.
+pub(crate) const PROP_PER_MOUNT_CLONE: &str = "zockervols:per-mount-clone";
+
+/// User property listing the options a clone took over from its origin,
+/// comma-separated.
+pub(crate) const PROP_INHERITED_OPTIONS: &str = "zockervols:inherited-options";
+
+/// User properties recording where a volume came from: the volume and
+/// snapshot it was cloned from, and why it was created. Unlike `origin`,
+/// these survive promoting and renaming datasets.
+pub(crate) const PROP_SOURCE_VOLUME: &str = "zockervols:source-volume";
+pub(crate) const PROP_SOURCE_SNAPSHOT: &str = "zockervols:source-snapshot";
+pub(crate) const PROP_REASON: &str = "zockervols:reason";
+
+/// User property holding the `auto-snapshot=` option of a volume.
+pub(crate) const PROP_AUTO_SNAPSHOT: &str = "zockervols:auto-snapshot";
+
+/// User property holding the `snapshot-on-unmount=` option of a volume, the
+/// number of snapshots taken on unmount to keep.
+pub(crate) const PROP_SNAPSHOT_ON_UNMOUNT: &str = "zockervols:snapshot-on-unmount";
+
+/// User property marking a volume created with `ephemeral=on`.
+pub(crate) const PROP_EPHEMERAL: &str = "zockervols:ephemeral";
+
+/// User property marking a volume created with `exclusive=on`.
+pub(crate) const PROP_EXCLUSIVE: &str = "zockervols:exclusive";
+
+/// User property naming the profile a volume was created with.
+pub(crate) const PROP_PROFILE: &str = "zockervols:profile";
+
+/// User property listing the `TUNABLES` a volume was created with,
+/// comma-separated.
+pub(crate) const PROP_TUNABLES: &str = "zockervols:tunables";
+
+/// Options a clone takes over from its origin, unless given explicitly. These
+/// are named the same as the properties they map to.
+const INHERITED_OPTIONS: &[&str] = &["refquota", "compression", "atime", "exec", "setuid"];
+
+/// Native properties which can be given as options of the same name. Unlike
+/// the ones in `INHERITED_OPTIONS`, they are only set if given, and are
+/// otherwise inherited from the root dataset.
+pub(crate) const TUNABLES: &[&str] = &[
+    "recordsize",
+    "sync",
+    "logbias",
+    "primarycache",
+    "xattr",
+    "acltype",
+    "dnodesize",
+    "copies",
+    "devices",
+    "quota",
+    "reservation",
+];
+
+pub(crate) enum Cmd {
+    Create { vol: String, opts: Box<VolumeOptions> },
     Destroy { vol: String },
     Mount { vol: String },
     Unmount { vol: String },
-    List,
-    GetMountpoint { vol: String },
-    Inspect { vol: String },
+    MountClone { vol: String, id: String },
+    UnmountClone { vol: String, id: String },
 }
 
 impl Cmd {
     fn create(vol: &str, opts: VolumeOptions) -> Self {
         Cmd::Create {
             vol: sanitize_vol(vol),
-            opts,
+            opts: Box::new(opts),
         }
     }
 
-    fn destroy(vol: &str) -> Self {
+    pub(crate) fn destroy(vol: &str) -> Self {
         Cmd::Destroy {
             vol: sanitize_vol(vol),
         }
     }
 
-    fn mount(vol: &str) -> Self {
+    pub(crate) fn mount(vol: &str) -> Self {
         Cmd::Mount {
             vol: sanitize_vol(vol),
         }
     }
 
-    fn unmount(vol: &str) -> Self {
+    pub(crate) fn unmount(vol: &str) -> Self {
         Cmd::Unmount {
             vol: sanitize_vol(vol),
         }
     }
 
-    fn list() -> Self {
-        Cmd::List
-    }
-
-    fn get_mountpoint(vol: &str) -> Self {
-        Cmd::GetMountpoint {
+    fn mount_clone(vol: &str, id: &str) -> Self {
+        Cmd::MountClone {
             vol: sanitize_vol(vol),
+            id: sanitize_vol(id),
         }
     }
 
-    fn inspect(vol: &str) -> Self {
-        Cmd::Inspect {
+    pub(crate) fn unmount_clone(vol: &str, id: &str) -> Self {
+        Cmd::UnmountClone {
             vol: sanitize_vol(vol),
+            id: sanitize_vol(id),
         }
     }
 
-    fn run(&self, root: &PathBuf) -> Result<Vec<u8>, Error> {
+    pub(crate) fn run(&self, root: &Path, zfs: &dyn ZfsBackend) -> Result<(), Error> {
         match self {
             Cmd::Create { vol, opts } => {
-                let dataset = root.join(vol);
-
-                match opts.snapshot_of {
-                    Some(ref from) => {
-                        // snapshot the `from` fs
-                        let snap = format!(
-                            "{}@{}",
-                            root.join(sanitize_vol(from)).to_str().unwrap(),
-                            SystemTime::now()
-                                .duration_since(SystemTime::UNIX_EPOCH)
-                                .expect("SystemTime before UNIX epoch!")
-                                .as_nanos()
-                        );
-                        ZfsCmd::User.run(|zfs| zfs.arg("snapshot").arg(&snap))?;
-                        // clone the snapshot as `vol`
-                        ZfsCmd::User
-                            .run(|zfs| {
-                                zfs.arg("clone")
-                                    .args(opts.as_args())
-                                    .args(&["-o", "mountpoint=none"])
-                                    .arg(snap.to_owned())
-                                    .arg(&dataset)
-                            })
-                            .or_else(|e| ignore_already_exists(e).and(Ok(vec![])))
-                            .or_else(|e| ignore_mount_error(e).and(Ok(vec![])))?;
-                        // finally, mark the snapshot for deletion
-                        ZfsCmd::User.run(|zfs| zfs.arg("destroy").arg("-d").arg(&snap))
+                let dataset = dataset_name(root, vol);
+                // Unless given a snapshot to clone, take one now
+                let snap = opts.snapshot_of.as_ref().map(|from| {
+                    let (from, snap) = split_source(from);
+                    let origin = dataset_name(root, &sanitize_vol(from));
+                    match snap {
+                        Some(snap) => (format!("{}@{}", origin, sanitize_vol(snap)), false),
+                        None => (snapshot_name(&origin), true),
                     }
-                    None => ZfsCmd::User
-                        .run(|zfs| {
-                            zfs.arg("create")
-                                .args(opts.as_args())
-                                .args(&["-o", "mountpoint=none"])
-                                .arg(&dataset)
-                        })
-                        .or_else(|e| ignore_mount_error(e).map(|_| vec![])),
-                }?;
+                });
+                let props = {
+                    let mut props = opts.as_properties();
+                    props.insert("mountpoint".to_string(), "none".to_string());
+                    props.insert(PROP_INCOMPLETE.to_string(), "on".to_string());
+                    if let Some(name) = snap.as_ref().and_then(|(snap, _)| snap.split('@').nth(1)) {
+                        props.insert(PROP_SOURCE_SNAPSHOT.to_string(), name.to_string());
+                    }
+                    props
+                };
+                let destroy_dataset = || zfs.destroy(&dataset, Destroy::Recursive);
+                let mountpoint = get_mountpoint_of(zfs, &root.to_string_lossy())?.join(vol);
+
+                // If any of the below fails, we roll back what has been done so
+                // far -- otherwise, the next attempt would find a dataset with
+                // bogus ownership which it considers to exist.
+                let mut tx = Transaction::new(vol);
+
+                match snap {
+                    Some((ref snap, fresh)) => {
+                        if fresh {
+                            tx.step(
+                                "snapshot",
+                                || zfs.snapshot(snap),
+                                move || zfs.destroy(snap, Destroy::Single),
+                            )?;
+                        }
+                        tx.step(
+                            "clone",
+                            || {
+                                zfs.clone_snapshot(snap, &dataset, &props)
+                                    .or_else(ignore_mount_error)
+                            },
+                            destroy_dataset,
+                        )?;
+                        // Mark a snapshot taken for the clone for deletion once
+                        // the clone is gone. This also makes undoing the
+                        // snapshot step redundant, so do it last.
+                        if fresh {
+                            tx.run("destroy -d", || zfs.destroy(snap, Destroy::Deferred))?;
+                        }
+                    },
+                    None => {
+                        tx.step(
+                            "create",
+                            || zfs.create(&dataset, &props).or_else(ignore_mount_error),
+                            destroy_dataset,
+                        )?;
+                    },
+                }
 
                 // ZoL can't delegate mount permissions (via allow), but we
                 // ultimately want the volume to be owned by the driver's user.
@@ -127,49 +219,40 @@ impl Cmd {
                 // permissions. In order to avoid dangling mountpoint
                 // directories, we also create the mountpoint here so it is
                 // owned by the driver.
-                let mountpoint = ZfsCmd::get_mountpoint_of(root)?.join(vol);
+                tx.step(
+                    "mkdir",
+                    || fs::create_dir(&mountpoint).map_err(|e| e.into()),
+                    || fs::remove_dir(&mountpoint).map_err(|e| e.into()),
+                )?;
 
-                fs::create_dir(&mountpoint)?;
+                tx.step(
+                    "mount",
+                    || set_mountpoint_of(zfs, &dataset, &mountpoint),
+                    || zfs.set(&dataset, "mountpoint", "none"),
+                )?;
 
-                ZfsCmd::set_mountpoint_of(&dataset, &mountpoint)?;
-
-                let res1 = {
-                    Command::new("sudo")
-                        .arg("chown")
-                        .arg({
-                            // Try hard to use username:groupname instead of uid:gid
-                            let mut user = get_effective_username()
-                                .unwrap_or_else(|| get_effective_uid().to_string().into());
-                            let group = get_effective_groupname()
-                                .unwrap_or_else(|| get_effective_gid().to_string().into());
-
-                            user.push(":");
-                            user.push(group);
-                            user
-                        })
-                        .arg(&mountpoint)
-                        .run()?;
+                tx.run("chown", || zfs.chown(&mountpoint))?;
 
+                tx.run("chmod", || {
                     fs::set_permissions(&mountpoint, fs::Permissions::from_mode(0o750))
-                };
+                        .map_err(|e| e.into())
+                })?;
 
-                // Unmount in any case
-                let res2 = ZfsCmd::remove_mountpoint_of(&dataset);
+                tx.run("unmount", || remove_mountpoint_of(zfs, &dataset))?;
 
-                match (res1, res2) {
-                    (Err(e), _) => Err(e.into()),
-                    (_, Err(e)) => Err(e),
-                    (Ok(_), Ok(_)) => Ok(vec![]),
-                }
-            }
+                // Only now the volume is good to use
+                tx.run("finish", || zfs.inherit(&dataset, PROP_INCOMPLETE))?;
+
+                tx.commit();
+                Ok(())
+            },
 
             Cmd::Destroy { vol } => {
-                let res =
-                    ZfsCmd::User.run(|zfs| zfs.args(&["destroy", "-r"]).arg(root.join(vol)))?;
+                zfs.destroy(&dataset_name(root, vol), Destroy::Recursive)?;
 
                 // Clean up the mountpoint if we failed to do so when unmounting
                 {
-                    let mountpoint = ZfsCmd::get_mountpoint_of(root)?.join(vol);
+                    let mountpoint = get_mountpoint_of(zfs, &root.to_string_lossy())?.join(vol);
                     if mountpoint.exists() {
                         fs::remove_dir(mountpoint)
                     } else {
@@ -180,116 +263,129 @@ impl Cmd {
                     error!("Cmd::Destroy: failed to remove mount directory: {:?}", e)
                 });
 
-                Ok(res)
+                Ok(())
             }
 
             Cmd::Mount { vol } => {
-                let mountpoint = ZfsCmd::get_mountpoint_of(root)?.join(vol);
+                let mountpoint = get_mountpoint_of(zfs, &root.to_string_lossy())?.join(vol);
+
+                // The directory may have been left behind by a crash
+                fs::create_dir(&mountpoint).or_else(|e| match e.kind() {
+                    io::ErrorKind::AlreadyExists => Ok(()),
+                    _ => Err(e),
+                })?;
+                set_mountpoint_of(zfs, &dataset_name(root, vol), &mountpoint)
+            }
 
-                fs::create_dir(&mountpoint)?;
-                ZfsCmd::set_mountpoint_of(&root.join(vol), &mountpoint)?;
+            Cmd::Unmount { vol } => remove_mountpoint_of(zfs, &dataset_name(root, vol)),
+
+            Cmd::MountClone { vol, id } => {
+                let dataset = dataset_name(root, vol);
+                let clone = clone_name(root, vol, id);
+                let mountpoint = get_mountpoint_of(zfs, &root.to_string_lossy())?
+                    .join(vol)
+                    .join(id);
+                let snap = latest_snapshot(zfs, &dataset)?;
+                // Everything else is inherited from the volume
+                let props = {
+                    let mut props = zfs.get(&dataset, &["refquota"])?;
+                    props.insert("mountpoint".to_string(), "none".to_string());
+                    props
+                };
 
-                Ok(vec![])
-            }
+                let mut tx = Transaction::new(vol);
+                tx.step(
+                    "clone",
+                    || zfs.clone_snapshot(&snap, &clone, &props),
+                    || zfs.destroy(&clone, Destroy::Single),
+                )?;
+                tx.step(
+                    "mkdir",
+                    || fs::create_dir_all(&mountpoint).map_err(|e| e.into()),
+                    || fs::remove_dir(&mountpoint).map_err(|e| e.into()),
+                )?;
+                tx.run("mount", || set_mountpoint_of(zfs, &clone, &mountpoint))?;
+
+                tx.commit();
+                Ok(())
+            },
 
-            Cmd::Unmount { vol } => ZfsCmd::remove_mountpoint_of(&root.join(vol)).map(|()| vec![]),
-
-            Cmd::List => ZfsCmd::User.run(|zfs| {
-                zfs.arg("list")
-                    .args(&[
-                        "-H",
-                        "-p",
-                        "-r",
-                        "-o",
-                        "name,mountpoint,creation,used,avail",
-                    ])
-                    .arg(root)
-            }),
-
-            Cmd::GetMountpoint { vol } => ZfsCmd::User.run(|zfs| {
-                zfs.args(&["get", "mountpoint", "-H", "-o", "value"])
-                    .arg(root.join(vol))
-            }),
-
-            Cmd::Inspect { vol } => ZfsCmd::User.run(|zfs| {
-                zfs.arg("list")
-                    .args(&["-H", "-p", "-o", "name,mountpoint,creation,used,avail"])
-                    .arg(root.join(vol))
-            }),
-        }
-    }
-}
-
-enum ZfsCmd {
-    Sudo,
-    User,
-}
-
-impl ZfsCmd {
-    fn run<F>(&self, f: F) -> Result<Vec<u8>, Error>
-    where
-        F: FnOnce(&mut Command) -> &mut Command,
-    {
-        let sudo = self.sudo();
-        let mut base = Command::new(if sudo { "sudo" } else { "zfs" });
-        let cmd = {
-            let cmd = if sudo {
-                base.arg("zfs")
-            } else {
-                base.borrow_mut()
-            };
-            f(cmd)
-        };
-        cmd.run()
-    }
+            Cmd::UnmountClone { vol, id } => {
+                let clone = clone_name(root, vol, id);
+                remove_mountpoint_of(zfs, &clone)?;
+                zfs.destroy(&clone, Destroy::Single)?;
 
-    #[cfg(target_os = "linux")]
-    fn sudo(&self) -> bool {
-        if get_effective_uid() == 0 {
-            false
-        } else {
-            match self {
-                ZfsCmd::Sudo => true,
-                ZfsCmd::User => false,
-            }
+                // The volume's directory goes away with its last clone
+                let dir = get_mountpoint_of(zfs, &root.to_string_lossy())?.join(vol);
+                let _ = fs::remove_dir(dir);
+                Ok(())
+            },
         }
     }
+}
 
-    #[cfg(not(target_os = "linux"))]
-    fn sudo(&self) -> bool {
-        false
-    }
+fn dataset_name(root: &Path, vol: &str) -> String {
+    root.join(vol).to_string_lossy().into_owned()
+}
 
-    fn get_mountpoint_of(dataset: &Path) -> Result<PathBuf, Error> {
-        Self::User
-            .run(|zfs| {
-                zfs.args(&["get", "mountpoint", "-H", "-o", "value"])
-                    .arg(dataset)
-            })
-            .and_then(|stdout| {
-                as_pathbuf(stdout)
-                    .ok_or_else(|| Error::NoMountpointError(dataset.display().to_string()))
-            })
-    }
+/// The name of the private clone of `vol` mounted by `id`. Being a child of
+/// the volume keeps it out of volume listings.
+pub(crate) fn clone_name(root: &Path, vol: &str, id: &str) -> String {
+    root.join(vol).join(id).to_string_lossy().into_owned()
+}
 
-    fn set_mountpoint_of(dataset: &Path, mountpoint: &Path) -> Result<(), Error> {
-        Self::Sudo
-            .run(|zfs| {
-                zfs.arg("set")
-                    .arg(format!("mountpoint={}", mountpoint.display()))
-                    .arg(dataset)
-            })
-            .map(|_| ())
-    }
+fn snapshot_name(dataset: &str) -> String {
+    format!(
+        "{}@{}",
+        dataset,
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("SystemTime before UNIX epoch!")
+            .as_nanos()
+    )
+}
 
-    fn remove_mountpoint_of(dataset: &Path) -> Result<(), Error> {
-        let mountpoint = ZfsCmd::get_mountpoint_of(dataset)?;
-        Self::Sudo.run(|zfs| zfs.args(&["set", "mountpoint=none"]).arg(dataset))?;
-        fs::remove_dir(mountpoint)?;
-        Ok(())
+// The most recent snapshot of `dataset`, taking one if there is none yet or
+// the dataset has been written to since.
+fn latest_snapshot(zfs: &dyn ZfsBackend, dataset: &str) -> Result<String, Error> {
+    let prefix = format!("{}@", dataset);
+    let latest = zfs
+        .list(dataset, Kind::Snapshot, Some(1), &["name", "createtxg"])?
+        .into_iter()
+        .filter(|props| props["name"].starts_with(&prefix))
+        .max_by_key(|props| props["createtxg"].parse::<u64>().unwrap_or(0))
+        .map(|props| props["name"].clone());
+
+    match latest {
+        Some(snap) if zfs.get(dataset, &["written"])?["written"] == "0" => Ok(snap),
+        _ => {
+            let snap = snapshot_name(dataset);
+            zfs.snapshot(&snap)?;
+            Ok(snap)
+        },
     }
 }
 
+pub(crate) fn get_mountpoint_of(zfs: &dyn ZfsBackend, dataset: &str) -> Result<PathBuf, Error> {
+    zfs.get(dataset, &["mountpoint"]).and_then(|props| {
+        props
+            .get("mountpoint")
+            .and_then(|mp| as_pathbuf(mp))
+            .ok_or_else(|| Error::NoMountpointError(dataset.to_string()))
+    })
+}
+
+fn set_mountpoint_of(zfs: &dyn ZfsBackend, dataset: &str, mountpoint: &Path) -> Result<(), Error> {
+    zfs.set(dataset, "mountpoint", &mountpoint.to_string_lossy())
+}
+
+pub(crate) fn remove_mountpoint_of(zfs: &dyn ZfsBackend, dataset: &str) -> Result<(), Error> {
+    let mountpoint = get_mountpoint_of(zfs, dataset)?;
+    zfs.set(dataset, "mountpoint", "none")?;
+    fs::remove_dir(mountpoint)?;
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum Error {
     IoError(io::Error),
@@ -297,9 +393,24 @@ pub enum Error {
     MountsLockError(String, String),
     CmdIoError(String, io::Error),
     CmdError(String, Vec<u8>),
+    CmdTimeoutError(String, time::Duration),
     CmdOutputParseError(csv::Error),
     VolumeOptionsError(OptsError),
     NoMountpointError(String),
+    MountTableError(String, String),
+    ConfigError(String, String),
+    TransactionError(String, &'static str, Box<Error>, Vec<(&'static str, Error)>),
+    WorkersBusyError,
+    LockTimeoutError(String, time::Duration),
+    NotACloneError(String),
+    HasClonesError(String, Vec<String>),
+    NoSuchVolumeError(String),
+    NoSuchSnapshotError(String, String),
+    NoSuchGenerationError(String, String),
+    NoGenerationsError(String),
+    VolExclusiveError(String, Vec<String>),
+    OptionNotAllowedError(String, String, String),
+    NoSuchProfileError(String),
 }
 
 impl From<io::Error> for Error {
@@ -329,9 +440,54 @@ impl From<Error> for ErrorResponse {
             Error::CmdError(cmd, stderr) => {
                 format!("{}: {}", cmd, String::from_utf8_lossy(&stderr).into_owned())
             }
+            Error::CmdTimeoutError(cmd, timeout) => {
+                format!("{}: killed after {}s", cmd, timeout.as_secs())
+            }
             Error::CmdOutputParseError(e) => e.to_string(),
             Error::VolumeOptionsError(e) => e.to_string(),
             Error::NoMountpointError(vol) => format!("No mountpoint for {}", vol),
+            Error::MountTableError(path, e) => format!("Mount table {}: {}", path, e),
+            Error::ConfigError(path, e) => format!("Config {}: {}", path, e),
+            Error::TransactionError(vol, step, e, left) if left.is_empty() => format!(
+                "Volume {}: {} failed, rolled back: {}",
+                vol,
+                step,
+                ErrorResponse::from(*e)
+            ),
+            Error::TransactionError(vol, step, e, left) => format!(
+                "Volume {}: {} failed: {}; rollback incomplete, left behind: {}",
+                vol,
+                step,
+                ErrorResponse::from(*e),
+                left.into_iter()
+                    .map(|(step, e)| format!("{} ({})", step, ErrorResponse::from(e)))
+                    .join(", ")
+            ),
+            Error::WorkersBusyError => "Too many requests in progress, try again later".to_string(),
+            Error::LockTimeoutError(vols, timeout) => format!(
+                "Timed out after {}s waiting for other operations on {} to finish",
+                timeout.as_secs(),
+                vols
+            ),
+            Error::NotACloneError(vol) => format!("Volume {} is not a clone of a volume", vol),
+            Error::HasClonesError(vol, clones) => {
+                format!("Volume {} has dependent clones: {}", vol, clones.join(", "))
+            }
+            Error::NoSuchVolumeError(vol) => format!("Volume {} does not exist", vol),
+            Error::NoSuchSnapshotError(vol, snap) => {
+                format!("Volume {} has no snapshot {}", vol, snap)
+            }
+            Error::NoSuchGenerationError(vol, gen) => {
+                format!("Volume {} has no generation {}", vol, gen)
+            }
+            Error::NoGenerationsError(vol) => format!("Volume {} has no previous generations", vol),
+            Error::VolExclusiveError(vol, by) => {
+                format!("Volume {} is mounted exclusively by: {}", vol, by.join(", "))
+            }
+            Error::OptionNotAllowedError(vol, opt, value) => {
+                format!("Option {}={} is not allowed for volume {}", opt, value, vol)
+            }
+            Error::NoSuchProfileError(profile) => format!("Profile {} does not exist", profile),
         };
         ErrorResponse { err }
     }
@@ -340,10 +496,20 @@ impl From<Error> for ErrorResponse {
 pub struct VolumeOptions {
     snapshot_of: Option<String>,
     refquota: u64,
-    enable_compression: bool,
+    compression: String,
     enable_atime: bool,
     enable_exec: bool,
     enable_setuid: bool,
+    ttl: Option<Duration>,
+    per_mount_clone: bool,
+    inherited: Vec<String>,
+    reason: Option<String>,
+    auto_snapshot: Option<autosnap::Spec>,
+    snapshot_on_unmount: Option<usize>,
+    ephemeral: bool,
+    exclusive: bool,
+    tunables: Properties,
+    profile: Option<String>,
 }
 
 impl Default for VolumeOptions {
@@ -351,45 +517,193 @@ impl Default for VolumeOptions {
         VolumeOptions {
             snapshot_of: None,
             refquota: 1024 * 1024 * 250, // 250MiB
-            enable_compression: true,
+            compression: "on".to_string(),
             enable_atime: false,
             enable_exec: false,
             enable_setuid: false,
+            ttl: None,
+            per_mount_clone: false,
+            inherited: Vec::new(),
+            reason: None,
+            auto_snapshot: None,
+            snapshot_on_unmount: None,
+            ephemeral: false,
+            exclusive: false,
+            tunables: HashMap::new(),
+            profile: None,
         }
     }
 }
 
 impl VolumeOptions {
-    fn as_properties<'a>(&self) -> HashMap<&'a str, String> {
+    fn as_properties(&self) -> Properties {
         fn onoff(b: bool) -> String {
             if b { "on" } else { "off" }.to_string()
         }
 
         let mut props = HashMap::new();
-        props.insert("refquota", self.refquota.to_string());
-        props.insert("compression", onoff(self.enable_compression));
-        props.insert("atime", onoff(self.enable_atime));
-        props.insert("exec", onoff(self.enable_exec));
-        props.insert("setuid", onoff(self.enable_setuid));
+        props.insert("refquota".to_string(), self.refquota.to_string());
+        props.insert("compression".to_string(), self.compression.clone());
+        props.insert("atime".to_string(), onoff(self.enable_atime));
+        props.insert("exec".to_string(), onoff(self.enable_exec));
+        props.insert("setuid".to_string(), onoff(self.enable_setuid));
+        if let Some(ttl) = self.ttl {
+            props.insert(PROP_TTL.to_string(), ttl.num_seconds().to_string());
+        }
+        if self.per_mount_clone {
+            props.insert(PROP_PER_MOUNT_CLONE.to_string(), onoff(true));
+        }
+        if !self.inherited.is_empty() {
+            props.insert(PROP_INHERITED_OPTIONS.to_string(), self.inherited.join(","));
+        }
+        if let Some(ref from) = self.snapshot_of {
+            props.insert(PROP_SOURCE_VOLUME.to_string(), sanitize_vol(split_source(from).0));
+        }
+        if let Some(ref reason) = self.reason {
+            props.insert(PROP_REASON.to_string(), reason.clone());
+        }
+        if let Some(ref spec) = self.auto_snapshot {
+            props.insert(PROP_AUTO_SNAPSHOT.to_string(), spec.to_string());
+        }
+        if let Some(keep) = self.snapshot_on_unmount {
+            props.insert(PROP_SNAPSHOT_ON_UNMOUNT.to_string(), keep.to_string());
+        }
+        if self.ephemeral {
+            props.insert(PROP_EPHEMERAL.to_string(), onoff(true));
+        }
+        if self.exclusive {
+            props.insert(PROP_EXCLUSIVE.to_string(), onoff(true));
+        }
+        if let Some(ref profile) = self.profile {
+            props.insert(PROP_PROFILE.to_string(), profile.clone());
+        }
+        if !self.tunables.is_empty() {
+            props.extend(self.tunables.clone());
+            props.insert(PROP_TUNABLES.to_string(), self.tunable_names().join(","));
+        }
 
         props
     }
 
-    fn as_args(&self) -> Vec<String> {
-        vec!["-o".to_string()]
-            .into_iter()
-            .chain(
-                self.as_properties()
-                    .iter()
-                    .map(|(k, v)| format!("{}={}", k, v))
-                    .intersperse("-o".to_string()),
-            )
+    /// The `TUNABLES` given, in the order they are listed there.
+    fn tunable_names(&self) -> Vec<&'static str> {
+        TUNABLES
+            .iter()
+            .filter(|prop| self.tunables.contains_key(**prop))
+            .cloned()
             .collect()
     }
+
+    /// Recover the options a dataset was created with from its properties.
+    fn from_properties(props: &Properties) -> Self {
+        let def = VolumeOptions::default();
+        let enabled = |prop: &str, def: bool| {
+            props
+                .get(prop)
+                .map(|x| x != "off" && x != "-")
+                .unwrap_or(def)
+        };
+
+        VolumeOptions {
+            snapshot_of: props
+                .get("origin")
+                .and_then(|origin| origin.split('@').nth(0))
+                .filter(|ds| *ds != "-")
+                .map(basename),
+            refquota: props
+                .get("refquota")
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(def.refquota),
+            compression: props
+                .get("compression")
+                .filter(|x| *x != "-")
+                .cloned()
+                .unwrap_or(def.compression),
+            enable_atime: enabled("atime", def.enable_atime),
+            enable_exec: enabled("exec", def.enable_exec),
+            enable_setuid: enabled("setuid", def.enable_setuid),
+            ttl: props.get(PROP_TTL).and_then(|x| parse_duration(x)),
+            per_mount_clone: enabled(PROP_PER_MOUNT_CLONE, def.per_mount_clone),
+            inherited: props
+                .get(PROP_INHERITED_OPTIONS)
+                .filter(|x| *x != "-")
+                .map(|x| x.split(',').map(String::from).collect())
+                .unwrap_or_default(),
+            reason: props.get(PROP_REASON).filter(|x| *x != "-").cloned(),
+            auto_snapshot: props.get(PROP_AUTO_SNAPSHOT).and_then(|x| x.parse().ok()),
+            snapshot_on_unmount: props.get(PROP_SNAPSHOT_ON_UNMOUNT).and_then(|x| x.parse().ok()),
+            ephemeral: enabled(PROP_EPHEMERAL, def.ephemeral),
+            exclusive: enabled(PROP_EXCLUSIVE, def.exclusive),
+            // Whatever else `zfs get` reports is not an option of the volume
+            tunables: props
+                .get(PROP_TUNABLES)
+                .map(|names| names.split(',').collect::<Vec<_>>())
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|name| props.get_key_value(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            profile: props.get(PROP_PROFILE).filter(|x| *x != "-").cloned(),
+        }
+    }
+}
+
+// Formats the options the way they would be passed to `docker volume create`
+impl Display for VolumeOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn onoff(b: bool) -> &'static str {
+            if b {
+                "on"
+            } else {
+                "off"
+            }
+        }
+
+        if let Some(ref profile) = self.profile {
+            write!(f, "profile={},", profile)?;
+        }
+        if let Some(ref from) = self.snapshot_of {
+            write!(f, "from={},", from)?;
+        }
+        write!(
+            f,
+            "refquota={},compression={},atime={},exec={},setuid={}",
+            self.refquota,
+            self.compression,
+            onoff(self.enable_atime),
+            onoff(self.enable_exec),
+            onoff(self.enable_setuid)
+        )?;
+        for name in self.tunable_names() {
+            write!(f, ",{}={}", name, self.tunables[name])?;
+        }
+        if let Some(ttl) = self.ttl {
+            write!(f, ",ttl={}", format_duration(ttl))?;
+        }
+        if self.per_mount_clone {
+            write!(f, ",per-mount-clone=on")?;
+        }
+        if let Some(ref reason) = self.reason {
+            write!(f, ",reason={}", reason)?;
+        }
+        if let Some(ref spec) = self.auto_snapshot {
+            write!(f, ",auto-snapshot={}", spec)?;
+        }
+        if let Some(keep) = self.snapshot_on_unmount {
+            write!(f, ",snapshot-on-unmount={}", keep)?;
+        }
+        if self.ephemeral {
+            write!(f, ",ephemeral=on")?;
+        }
+        if self.exclusive {
+            write!(f, ",exclusive=on")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
-pub struct OptsError(&'static str);
+pub struct OptsError(pub(crate) &'static str);
 
 impl Display for OptsError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -403,153 +717,801 @@ impl From<OptsError> for Error {
     }
 }
 
+pub(crate) fn parse_refquota(x: &str) -> Result<u64, OptsError> {
+    Byte::from_str(x)
+        .map_err(|_| OptsError("Invalid refquota specified"))
+        .and_then(|byte| {
+            u64::try_from(byte.get_bytes()).map_err(|_| OptsError("Quota out of range"))
+        })
+}
+
+fn parse_compression(x: &str) -> Result<String, OptsError> {
+    let level = |prefix: &str, levels: &[u32]| {
+        x.starts_with(prefix) && x[prefix.len()..].parse().map_or(false, |n| levels.contains(&n))
+    };
+    let zstd_fast = (1..=10).chain((20..=100).step_by(10)).chain(vec![500, 1000]);
+    let valid = match x {
+        "on" | "off" | "lz4" | "lzjb" | "zle" | "gzip" | "zstd" | "zstd-fast" => true,
+        _ => {
+            level("gzip-", &(1..=9).collect::<Vec<_>>())
+                || level("zstd-", &(1..=19).collect::<Vec<_>>())
+                || level("zstd-fast-", &zstd_fast.collect::<Vec<_>>())
+        },
+    };
+    if valid {
+        Ok(x.to_string())
+    } else {
+        Err(OptsError("Invalid compression specified"))
+    }
+}
+
+/// Validate the value of one of the `TUNABLES`, normalizing sizes to bytes.
+fn parse_tunable(prop: &str, x: &str) -> Result<String, OptsError> {
+    let one_of = |values: &[&str], err| {
+        if values.contains(&x) {
+            Ok(x.to_string())
+        } else {
+            Err(OptsError(err))
+        }
+    };
+    let size = |err| match x {
+        "none" => Ok(x.to_string()),
+        _ => parse_refquota(x)
+            .map(|bytes| bytes.to_string())
+            .map_err(|_| OptsError(err)),
+    };
+
+    match prop {
+        // Powers of two only, hence `16KiB` rather than `16K`
+        "recordsize" => parse_refquota(x)
+            .ok()
+            .filter(|bytes| bytes.is_power_of_two() && *bytes >= 512 && *bytes <= 16 << 20)
+            .map(|bytes| bytes.to_string())
+            .ok_or(OptsError("Invalid recordsize specified")),
+        "sync" => one_of(&["standard", "always", "disabled"], "Invalid sync specified"),
+        "logbias" => one_of(&["latency", "throughput"], "Invalid logbias specified"),
+        "primarycache" => one_of(&["all", "none", "metadata"], "Invalid primarycache specified"),
+        "xattr" => one_of(&["on", "off", "sa", "dir"], "Invalid xattr specified"),
+        "acltype" => one_of(
+            &["off", "noacl", "posixacl", "posix", "nfsv4"],
+            "Invalid acltype specified",
+        ),
+        "dnodesize" => one_of(
+            &["legacy", "auto", "1k", "2k", "4k", "8k", "16k"],
+            "Invalid dnodesize specified",
+        ),
+        "copies" => one_of(&["1", "2", "3"], "Invalid copies specified"),
+        "devices" => one_of(&["on", "off"], "Invalid devices specified"),
+        "quota" => size("Invalid quota specified"),
+        "reservation" => size("Invalid reservation specified"),
+        _ => Err(OptsError("Unknown property")),
+    }
+}
+
 // `TryFrom` is used for parsing user options
 impl TryFrom<HashMap<String, String>> for VolumeOptions {
     type Error = OptsError;
 
     fn try_from(opts: HashMap<String, String>) -> Result<Self, Self::Error> {
-        let def = VolumeOptions::default();
+        VolumeOptions::parse(opts, VolumeOptions::default())
+    }
+}
+
+/// The volume a clone is to be created from, as given by the user.
+fn snapshot_of(opts: &HashMap<String, String>) -> Option<&String> {
+    opts.get("snapshot-of").or_else(|| opts.get("from"))
+}
+
+/// Split the source of a clone, `volume[@snapshot]`, into its parts.
+fn split_source(from: &str) -> (&str, Option<&str>) {
+    let mut parts = from.splitn(2, '@');
+    (parts.next().unwrap_or_default(), parts.next())
+}
+
+impl VolumeOptions {
+    /// Parse user options, falling back to `def` for those not given.
+    fn parse(opts: HashMap<String, String>, def: VolumeOptions) -> Result<Self, OptsError> {
 
         let refquota = match opts.get("refquota") {
-            Some(x) => Byte::from_str(x)
-                .map_err(|_| OptsError("Invalid refquota specified"))
-                .and_then(|byte| {
-                    u64::try_from(byte.get_bytes()).map_err(|_| OptsError("Quota out of range"))
-                }),
+            Some(x) => parse_refquota(x),
             None => Ok(def.refquota),
         }?;
 
+        let ttl = match opts.get("ttl") {
+            Some(x) => parse_duration(x)
+                .filter(|ttl| *ttl > Duration::zero())
+                .map(Some)
+                .ok_or(OptsError("Invalid ttl specified")),
+            None => Ok(def.ttl),
+        }?;
+
+        let compression = match opts.get("compression") {
+            Some(x) => parse_compression(x),
+            None => Ok(def.compression),
+        }?;
+
+        let mut tunables = def.tunables;
+        for prop in TUNABLES {
+            if let Some(x) = opts.get(*prop) {
+                tunables.insert((*prop).to_string(), parse_tunable(prop, x)?);
+            }
+        }
+
+        let auto_snapshot = match opts.get("auto-snapshot") {
+            Some(x) => x.parse().map(Some),
+            None => Ok(def.auto_snapshot),
+        }?;
+
+        let snapshot_on_unmount = match opts.get("snapshot-on-unmount") {
+            Some(x) => x
+                .parse()
+                .ok()
+                .filter(|keep| *keep > 0)
+                .map(Some)
+                .ok_or(OptsError("Invalid snapshot-on-unmount specified")),
+            None => Ok(def.snapshot_on_unmount),
+        }?;
+
         fn option_enabled(opts: &HashMap<String, String>, opt: &str, def: bool) -> bool {
             opts.get(opt).map(|x| x == "on").unwrap_or(def)
         }
 
+        let per_mount_clone = option_enabled(&opts, "per-mount-clone", def.per_mount_clone);
+        // The private clones are gone after unmounting, there's nothing to
+        // snapshot
+        if per_mount_clone && snapshot_on_unmount.is_some() {
+            return Err(OptsError("snapshot-on-unmount can't be combined with per-mount-clone"));
+        }
+        let exclusive = option_enabled(&opts, "exclusive", def.exclusive);
+        // Private clones are exclusive as it is
+        if per_mount_clone && exclusive {
+            return Err(OptsError("exclusive can't be combined with per-mount-clone"));
+        }
+
         Ok(VolumeOptions {
-            snapshot_of: opts
-                .get("snapshot-of")
-                .or_else(|| opts.get("from"))
-                .cloned(),
+            snapshot_of: snapshot_of(&opts).cloned(),
             refquota,
-            enable_compression: option_enabled(&opts, "compression", def.enable_compression),
+            compression,
             enable_atime: option_enabled(&opts, "atime", def.enable_atime),
             enable_exec: option_enabled(&opts, "exec", def.enable_exec),
             enable_setuid: option_enabled(&opts, "setuid", def.enable_setuid),
+            ttl,
+            per_mount_clone,
+            inherited: def.inherited,
+            // Clones say why they were made, unless told otherwise
+            reason: opts
+                .get("reason")
+                .cloned()
+                .or_else(|| snapshot_of(&opts).map(|_| "clone".to_string())),
+            auto_snapshot,
+            snapshot_on_unmount,
+            ephemeral: option_enabled(&opts, "ephemeral", def.ephemeral),
+            exclusive,
+            tunables,
+            profile: opts.get("profile").cloned(),
         })
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Zfs {
-    root: PathBuf,
-    mounts: Arc<CHashMap<String, HashSet<String>>>,
+    pub(crate) root: PathBuf,
+    pub(crate) mounts: Arc<Mounts>,
+    pub(crate) backend: Arc<dyn ZfsBackend>,
+    pub(crate) locks: Arc<Locks>,
+    pub(crate) with_clones: WithClones,
+    pub(crate) exclusive_wait: Option<time::Duration>,
+    pub(crate) option_policy: Arc<options::Policy>,
+    pub(crate) profiles: Arc<Profiles>,
 }
 
+/// How long to wait for other operations on a volume by default.
+const LOCK_TIMEOUT: time::Duration = time::Duration::from_secs(120);
+
+/// How often to check whether an exclusive volume has been released.
+const EXCLUSIVE_POLL: time::Duration = time::Duration::from_millis(200);
+
 impl Zfs {
-    pub fn new(root: PathBuf) -> Self {
+    pub fn new(root: PathBuf, mounts: Mounts, backend: Arc<dyn ZfsBackend>) -> Self {
         Self {
             root,
-            mounts: Arc::new(CHashMap::new()),
+            mounts: Arc::new(mounts),
+            backend,
+            locks: Arc::new(Locks::new(Some(LOCK_TIMEOUT))),
+            with_clones: WithClones::default(),
+            exclusive_wait: Some(time::Duration::from_secs(0)),
+            option_policy: Arc::new(options::Policy::default()),
+            profiles: Arc::new(Profiles::new()),
+        }
+    }
+
+    /// The option sets `profile=` may name.
+    pub fn profiles(self, profiles: Profiles) -> Self {
+        Self {
+            profiles: Arc::new(profiles),
+            ..self
+        }
+    }
+
+    /// Restrict the options volumes may be created with.
+    pub fn option_policy(self, policy: options::Policy) -> Self {
+        Self {
+            option_policy: Arc::new(policy),
+            ..self
+        }
+    }
+
+    /// Wait at most `wait` for an `exclusive=on` volume to be unmounted by
+    /// its current holder, or forever if `None`.
+    pub fn exclusive_wait(self, exclusive_wait: Option<time::Duration>) -> Self {
+        Self {
+            exclusive_wait,
+            ..self
+        }
+    }
+
+    /// What to do when removing a volume which has been cloned.
+    pub fn with_clones(self, with_clones: WithClones) -> Self {
+        Self {
+            with_clones,
+            ..self
+        }
+    }
+
+    /// Wait at most `timeout` for other operations on a volume to finish, or
+    /// forever if `None`.
+    pub fn lock_timeout(self, timeout: Option<time::Duration>) -> Self {
+        Self {
+            locks: Arc::new(Locks::new(timeout)),
+            ..self
+        }
+    }
+
+    /// Lock volumes `names` against concurrent lifecycle operations.
+    pub(crate) fn lock(&self, names: &[&str]) -> Result<Guard, Error> {
+        let vols = names.iter().map(|name| sanitize_vol(name)).collect::<Vec<_>>();
+        self.locks
+            .acquire(&vols.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    /// The name of the dataset backing volume `name`.
+    pub(crate) fn dataset(&self, name: &str) -> String {
+        dataset_name(&self.root, &sanitize_vol(name))
+    }
+
+    pub(crate) fn exists(&self, name: &str) -> Result<bool, Error> {
+        self.dataset_exists(&self.dataset(name))
+    }
+
+    // Docker still knows about volumes the GC removed behind its back
+    fn check_exists(&self, name: &str) -> Result<(), Error> {
+        if self.exists(name)? {
+            Ok(())
+        } else {
+            Err(Error::NoSuchVolumeError(name.to_string()))
         }
     }
 
-    fn exists(&self, name: &str) -> Result<bool, Error> {
-        self.get_mountpoint(name)
+    pub(crate) fn dataset_exists(&self, dataset: &str) -> Result<bool, Error> {
+        self.backend
+            .get(dataset, &["name"])
             .and(Ok(true))
             .or_else(|e| ignore_does_not_exist(e).map(|_| false))
     }
 
-    fn do_create(&self, name: &str, opts: HashMap<String, String>) -> Result<(), Error> {
-        let vopts = VolumeOptions::try_from(opts)?;
-        Cmd::create(name, vopts).run(&self.root).and(Ok(()))
+    /// The owners of the volume backed by dataset `vol`. The mount table is
+    /// keyed by the names Docker uses, which may not be sanitized yet.
+    pub(crate) fn owners_of(&self, vol: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .mounts
+            .all()?
+            .into_iter()
+            .filter(|(name, _)| sanitize_vol(name) == vol)
+            .flat_map(|(_, by)| by)
+            .sorted()
+            .collect())
+    }
+
+    fn do_create(&self, name: &str, given: HashMap<String, String>) -> Result<(), Error> {
+        let opts = self.with_profile(&given)?;
+        let source = snapshot_of(&opts).map(|from| split_source(from));
+        let origin = source.map(|(from, _)| from.to_string());
+        // Keep the origin from going away while we're cloning it
+        let mut vols = vec![name];
+        vols.extend(origin.as_ref().map(String::as_str));
+        let _lock = self.lock(&vols)?;
+
+        // Creating an existing volume is not an error, regardless of options
+        if self.exists(name)? {
+            return Ok(());
+        }
+
+        // Profiles are up to the administrator, only what the user asked
+        // for is subject to the policy
+        self.option_policy.check(name, &given)?;
+
+        if let Some((from, snap)) = source {
+            if !self.exists(from)? {
+                return Err(Error::NoSuchVolumeError(from.to_string()));
+            }
+            if let Some(snap) = snap {
+                let snapshot = format!("{}@{}", self.dataset(from), sanitize_vol(snap));
+                if !self.dataset_exists(&snapshot)? {
+                    return Err(Error::NoSuchSnapshotError(from.to_string(), snap.to_string()));
+                }
+            }
+        }
+
+        let vopts = match origin {
+            None => VolumeOptions::try_from(opts)?,
+            Some(origin) => {
+                let props = INHERITED_OPTIONS
+                    .iter()
+                    .chain(TUNABLES)
+                    .chain(&[PROP_TUNABLES])
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let mut def = VolumeOptions::from_properties(
+                    &self.backend.get(&self.dataset(&origin), &props)?,
+                );
+                // Tunables count only if they were given to the origin
+                def.inherited = INHERITED_OPTIONS
+                    .iter()
+                    .cloned()
+                    .chain(def.tunable_names())
+                    .filter(|opt| !opts.contains_key(*opt))
+                    .map(String::from)
+                    .collect();
+                let vopts = VolumeOptions::parse(opts, def)?;
+                // Or a clone would get around the policy by way of its origin.
+                // Defaults would be there had the origin been given nothing.
+                let props = vopts.as_properties();
+                let defaults = VolumeOptions::default().as_properties();
+                let inherited = vopts
+                    .inherited
+                    .iter()
+                    .filter_map(|opt| props.get_key_value(opt))
+                    .filter(|(opt, value)| defaults.get(*opt) != Some(*value))
+                    .map(|(opt, value)| (opt.clone(), value.clone()))
+                    .collect();
+                self.option_policy.check(name, &inherited)?;
+                vopts
+            },
+        };
+        Cmd::create(name, vopts).run(&self.root, &*self.backend)
+    }
+
+    /// The options of the profile named by `profile=`, if any, overridden by
+    /// those given explicitly.
+    fn with_profile(
+        &self,
+        opts: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, Error> {
+        let mut expanded = match opts.get("profile") {
+            None => HashMap::new(),
+            Some(profile) => self
+                .profiles
+                .get(profile)
+                .cloned()
+                .ok_or_else(|| Error::NoSuchProfileError(profile.clone()))?,
+        };
+        expanded.extend(opts.clone());
+        Ok(expanded)
     }
 
     fn do_remove(&self, name: &str) -> Result<(), Error> {
-        match self.mounts.get(name) {
-            Some(ref by) if !by.is_empty() => Err(Error::VolInUseError(
-                name.to_string(),
-                by.iter().cloned().collect(),
-            )),
+        let _lock = self.lock(&[name])?;
+        let owners = self.owners_of(&sanitize_vol(name))?;
+        if !owners.is_empty() {
+            return Err(Error::VolInUseError(name.to_string(), owners));
+        }
+        // Collected and ephemeral volumes are gone by the time Docker gets
+        // around to it
+        if !self.exists(name)? {
+            return Ok(());
+        }
+
+        self.destroy_volume(name)
+    }
 
-            _ => Cmd::destroy(name).run(&self.root).and(Ok(())),
+    /// Destroy volume `name`, dealing with volumes cloned from it according
+    /// to the `with_clones` setting.
+    pub(crate) fn destroy_volume(&self, name: &str) -> Result<(), Error> {
+        let dataset = self.dataset(name);
+        let origin = self.backend.get(&dataset, &["origin"])?["origin"].clone();
+
+        let clones = self.dependents(&dataset)?;
+        if let Some(newest) = clones.last() {
+            match self.with_clones {
+                WithClones::Refuse => {
+                    return Err(Error::HasClonesError(
+                        name.to_string(),
+                        clones.iter().map(|clone| basename(clone)).collect(),
+                    ))
+                },
+                WithClones::Promote => {
+                    info!("Volume {}: promoting {} before removal", name, newest);
+                    self.backend.promote(newest)?;
+                },
+                WithClones::Defer => {
+                    // Out of the way of a new volume of the same name, and
+                    // out of sight
+                    let hidden = format!(
+                        "{}.removed-{}",
+                        sanitize_vol(name),
+                        Utc::now().format("%Y%m%d%H%M%S%6f")
+                    );
+                    info!("Volume {}: deferring removal until its clones are gone", name);
+                    return self.backend.rename(&dataset, &dataset_name(&self.root, &hidden));
+                },
+            }
         }
+
+        Cmd::destroy(name).run(&self.root, &*self.backend)?;
+        self.reap(&origin);
+        Ok(())
     }
 
-    fn do_mount(&self, name: &str, caller: &str) -> Result<PathBuf, Error> {
-        Cmd::mount(name).run(&self.root).and_then(|_| {
-            self.get_mountpoint(name).map(|mountpoint| {
-                self.mounts.alter(name.to_string(), |old| {
-                    let mut owners = old.unwrap_or_default();
-                    owners.insert(caller.to_string());
-                    Some(owners)
-                });
-                mountpoint
+    // Clones of the snapshots of `dataset` which would prevent destroying it,
+    // oldest snapshot first.
+    fn dependents(&self, dataset: &str) -> Result<Vec<String>, Error> {
+        let below = format!("{}/", dataset);
+        Ok(self
+            .backend
+            .list(dataset, Kind::Snapshot, None, &["clones"])?
+            .into_iter()
+            .flat_map(|props| {
+                props["clones"]
+                    .split(',')
+                    .filter(|clone| !clone.is_empty() && *clone != "-")
+                    .map(String::from)
+                    .collect::<Vec<_>>()
             })
-        })
+            .filter(|clone| !clone.starts_with(&below))
+            .collect())
+    }
+
+    // Destroy the origin of a volume just removed, if its own removal was
+    // deferred and this was its last clone.
+    fn reap(&self, origin: &str) {
+        let fs = origin.split('@').next().unwrap_or_default();
+        if Path::new(fs).parent() != Some(&self.root) || is_volume(&basename(fs)) {
+            return;
+        }
+        match self.dependents(fs) {
+            Ok(ref clones) if clones.is_empty() => self
+                .backend
+                .destroy(fs, Destroy::Recursive)
+                .unwrap_or_else(|e| warn!("Failed to destroy {}: {:?}", fs, e)),
+            Ok(_) => {},
+            Err(e) => warn!("Failed to destroy {}: {:?}", fs, e),
+        }
+    }
+
+    fn do_mount(&self, name: &str, caller: &str) -> Result<PathBuf, Error> {
+        let deadline = self.exclusive_wait.map(|wait| Instant::now() + wait);
+        let lock = self.lock(&[name])?;
+        if self.per_mount_clone(name)? {
+            return self.do_mount_clone(name, caller);
+        }
+
+        let _lock = match self.held_exclusively(name, caller)? {
+            None => lock,
+            Some(holders) => {
+                if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                    return Err(Error::VolExclusiveError(name.to_string(), holders));
+                }
+                // Let the holders unmount in the meantime, without holding
+                // up requests for other volumes
+                drop(lock);
+                pool::blocking(|| self.wait_exclusive(name, caller, deadline))?
+            },
+        };
+
+        // Only the first owner actually mounts the dataset, everyone else
+        // shares the mountpoint. Unless the owner is recorded, it is unmounted
+        // again.
+        let mut tx = Transaction::new(name);
+        if self.owners_of(&sanitize_vol(name))?.is_empty() {
+            tx.step(
+                "mount",
+                || Cmd::mount(name).run(&self.root, &*self.backend),
+                || Cmd::unmount(name).run(&self.root, &*self.backend),
+            )?;
+        }
+
+        let mountpoint = self.get_mountpoint(name)?;
+        self.mounts.insert(name, caller)?;
+        tx.commit();
+        self.record_mount(name, caller);
+        Ok(mountpoint)
     }
 
     fn do_unmount(&self, name: &str, caller: &str) -> Result<(), Error> {
-        if let Some(mut owners) = self.mounts.get_mut(name) {
-            owners.remove(caller);
+        let _lock = self.lock(&[name])?;
+        let unused = if self.per_mount_clone(name)? {
+            if self.mounts.owners(name)?.contains(caller) {
+                Cmd::unmount_clone(name, caller).run(&self.root, &*self.backend)?;
+            }
+            self.mounts.remove(name, caller)?;
+            self.owners_of(&sanitize_vol(name))?.is_empty()
+        } else {
+            if let Some(keep) = self.snapshot_on_unmount(name)? {
+                if self.mounts.owners(name)?.contains(caller) {
+                    // Not worth failing the unmount over
+                    self.snapshot_unmounted(name, caller, keep).unwrap_or_else(|e| {
+                        warn!("Failed to snapshot {} on unmount: {:?}", name, e)
+                    });
+                }
+            }
+            self.mounts.remove(name, caller)?;
+            let remaining = self.owners_of(&sanitize_vol(name))?;
+            // The dataset stays mounted for the remaining owners
+            if !remaining.is_empty() {
+                self.record_unmount(name);
+                return Err(Error::VolInUseError(name.to_string(), remaining));
+            }
+            Cmd::unmount(name).run(&self.root, &*self.backend)?;
+            true
+        };
+        self.record_unmount(name);
+
+        // Should this fail, reconcile takes care of it at the next startup
+        if unused && self.ephemeral(name)? {
+            info!("Volume {}: destroying ephemeral volume", name);
+            self.destroy_volume(name)
+                .unwrap_or_else(|e| warn!("Failed to destroy {}: {:?}", name, e));
         }
+        Ok(())
+    }
 
-        match self.mounts.get(name) {
-            Some(ref by) if !by.is_empty() => Err(Error::VolInUseError(
-                name.to_string(),
-                by.iter().cloned().collect(),
-            )),
+    // The owners of `name`, if it is an `exclusive=on` volume held by
+    // somebody other than `caller`.
+    fn held_exclusively(&self, name: &str, caller: &str) -> Result<Option<Vec<String>>, Error> {
+        let owners = self.owners_of(&sanitize_vol(name))?;
+        let shared = owners.is_empty() || owners.iter().any(|owner| owner == caller);
+        if shared || !self.exclusive(name)? {
+            return Ok(None);
+        }
+        Ok(Some(owners))
+    }
 
-            _ => Cmd::unmount(name).run(&self.root).and(Ok(())),
+    // Wait for the holders of exclusive volume `name` to unmount it, polling
+    // as there's no telling when they do.
+    fn wait_exclusive(
+        &self,
+        name: &str,
+        caller: &str,
+        deadline: Option<Instant>,
+    ) -> Result<Guard, Error> {
+        loop {
+            thread::sleep(EXCLUSIVE_POLL);
+            let lock = self.lock(&[name])?;
+            match self.held_exclusively(name, caller)? {
+                None => return Ok(lock),
+                Some(holders) => {
+                    if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                        return Err(Error::VolExclusiveError(name.to_string(), holders));
+                    }
+                },
+            }
         }
     }
 
-    fn get_mountpoint(&self, name: &str) -> Result<PathBuf, Error> {
-        Cmd::get_mountpoint(name)
-            .run(&self.root)
-            .and_then(|stdout| {
-                as_pathbuf(stdout).ok_or_else(|| Error::NoMountpointError(name.to_string()))
-            })
+    fn exclusive(&self, name: &str) -> Result<bool, Error> {
+        self.backend
+            .get(&self.dataset(name), &[PROP_EXCLUSIVE])
+            .map(|props| props[PROP_EXCLUSIVE] == "on")
+    }
+
+    fn ephemeral(&self, name: &str) -> Result<bool, Error> {
+        self.backend
+            .get(&self.dataset(name), &[PROP_EPHEMERAL])
+            .map(|props| props[PROP_EPHEMERAL] == "on")
+    }
+
+    fn snapshot_on_unmount(&self, name: &str) -> Result<Option<usize>, Error> {
+        self.backend
+            .get(&self.dataset(name), &[PROP_SNAPSHOT_ON_UNMOUNT])
+            .map(|props| props[PROP_SNAPSHOT_ON_UNMOUNT].parse().ok())
     }
 
-    fn inspect(&self, name: &str) -> Result<Dataset, Error> {
-        let out = Cmd::inspect(name).run(&self.root)?;
-        parse_dataset(&out)
-            .map(|mut ds| {
-                ds.name = Path::new(&ds.name)
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
-                    .into_owned();
-                ds
+    // Preserve the state `caller` leaves the volume in, keeping the `keep`
+    // most recent such snapshots.
+    fn snapshot_unmounted(&self, name: &str, caller: &str, keep: usize) -> Result<(), Error> {
+        let dataset = self.dataset(name);
+        let prefix = format!("{}@unmount-", dataset);
+        let snapshot = format!(
+            "{}{}-{}",
+            prefix,
+            Utc::now().format("%Y%m%d%H%M%S%6f"),
+            sanitize_vol(caller)
+        );
+        self.backend.snapshot(&snapshot)?;
+
+        // Snapshots which have been cloned go away along with their last clone
+        let taken = self
+            .backend
+            .list(&dataset, Kind::Snapshot, Some(1), &["name", "defer_destroy"])?
+            .into_iter()
+            .filter(|props| props["name"].starts_with(&prefix) && props["defer_destroy"] != "on")
+            .map(|props| props["name"].clone())
+            .sorted()
+            .collect::<Vec<_>>();
+        for old in taken.iter().rev().skip(keep) {
+            self.backend.destroy(old, Destroy::Deferred)?;
+        }
+        Ok(())
+    }
+
+    // With `per-mount-clone=on`, every caller gets a private clone of the
+    // volume's latest snapshot, which is discarded on unmount.
+    fn do_mount_clone(&self, name: &str, caller: &str) -> Result<PathBuf, Error> {
+        let mut tx = Transaction::new(name);
+        if !self.mounts.owners(name)?.contains(caller) {
+            tx.step(
+                "mount clone",
+                || Cmd::mount_clone(name, caller).run(&self.root, &*self.backend),
+                || Cmd::unmount_clone(name, caller).run(&self.root, &*self.backend),
+            )?;
+        }
+
+        let mountpoint = self.get_clone_mountpoint(name, caller)?;
+        self.mounts.insert(name, caller)?;
+        tx.commit();
+        self.record_mount(name, caller);
+        Ok(mountpoint)
+    }
+
+    pub(crate) fn per_mount_clone(&self, name: &str) -> Result<bool, Error> {
+        self.backend
+            .get(&self.dataset(name), &[PROP_PER_MOUNT_CLONE])
+            .map(|props| props.get(PROP_PER_MOUNT_CLONE).map(String::as_str) == Some("on"))
+    }
+
+    fn get_clone_mountpoint(&self, name: &str, caller: &str) -> Result<PathBuf, Error> {
+        let clone = clone_name(&self.root, &sanitize_vol(name), &sanitize_vol(caller));
+        get_mountpoint_of(&*self.backend, &clone).map_err(|e| match e {
+            Error::NoMountpointError(_) => Error::NoMountpointError(name.to_string()),
+            e => e,
+        })
+    }
+
+    fn get_path(&self, name: &str) -> Result<PathBuf, Error> {
+        self.check_exists(name)?;
+        if !self.per_mount_clone(name)? {
+            return self.get_mountpoint(name);
+        }
+        // There's no telling who is asking, so pick one of the clones
+        match self.mounts.owners(name)?.iter().min() {
+            Some(caller) => self.get_clone_mountpoint(name, caller),
+            None => Err(Error::NoMountpointError(name.to_string())),
+        }
+    }
+
+    // The mount history is merely informational, so failing to record it
+    // doesn't fail the (un)mount.
+
+    fn record_mount(&self, name: &str, caller: &str) {
+        let dataset = self.dataset(name);
+        self.backend
+            .get(&dataset, &[PROP_MOUNT_COUNT])
+            .and_then(|props| {
+                let count = props
+                    .get(PROP_MOUNT_COUNT)
+                    .and_then(|x| x.parse::<u64>().ok())
+                    .unwrap_or(0);
+                self.backend
+                    .set(&dataset, PROP_MOUNT_COUNT, &(count + 1).to_string())?;
+                self.backend
+                    .set(&dataset, PROP_LAST_MOUNTED, &Utc::now().timestamp().to_string())?;
+                self.backend.set(&dataset, PROP_LAST_CONTAINER, caller)
             })
-            .map_err(|e| e.into())
+            .unwrap_or_else(|e| warn!("Failed to record mount of volume {}: {:?}", name, e))
     }
 
-    fn inspect_all(&self) -> Result<Vec<Dataset>, Error> {
-        let out = Cmd::list().run(&self.root)?;
-        let mut rdr = csv::ReaderBuilder::new()
-            .delimiter(b'\t')
-            .has_headers(false)
-            .from_reader(out.as_slice());
-
-        let mut iter = rdr.deserialize();
-        iter.next();
-
-        let mut dss = Vec::new();
-        for rs in iter {
-            let ds: Result<Dataset, Error> = rs.map_err(|e| e.into());
-            match ds {
-                Ok(mut the_ds) => {
-                    the_ds.name = Path::new(&the_ds.name)
-                        .file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .into_owned()
-                        .to_string();
-                    dss.push(the_ds)
-                }
-                Err(e) => return Err(e),
+    pub(crate) fn record_unmount(&self, name: &str) {
+        self.backend
+            .set(
+                &self.dataset(name),
+                PROP_LAST_UNMOUNTED,
+                &Utc::now().timestamp().to_string(),
+            )
+            .unwrap_or_else(|e| warn!("Failed to record unmount of volume {}: {:?}", name, e))
+    }
+
+    pub(crate) fn get_mountpoint(&self, name: &str) -> Result<PathBuf, Error> {
+        get_mountpoint_of(&*self.backend, &self.dataset(name)).map_err(|e| match e {
+            Error::NoMountpointError(_) => Error::NoMountpointError(name.to_string()),
+            e => e,
+        })
+    }
+
+    fn inspect(&self, name: &str) -> Result<Volume, Error> {
+        self.check_exists(name)?;
+        let mounts = self.mounts.all()?;
+        let dataset = self.dataset(name);
+        let props = self
+            .backend
+            .list(&dataset, Kind::Filesystem, Some(0), &volume_props())?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::NoMountpointError(name.to_string()))?;
+        let mut vol = as_volume(&props, &mounts)?;
+
+        // Too costly to find out for every volume in `List`
+        if let Some(spec) = VolumeOptions::from_properties(&props).auto_snapshot {
+            vol.status
+                .get_or_insert_with(HashMap::new)
+                .extend(autosnap::status(&*self.backend, &dataset, &spec)?);
+        }
+        Ok(vol)
+    }
+
+    fn inspect_all(&self) -> Result<Vec<Volume>, Error> {
+        let mounts = self.mounts.all()?;
+        self.backend
+            .list(
+                &self.root.to_string_lossy(),
+                Kind::Filesystem,
+                Some(1),
+                &volume_props(),
+            )?
+            .iter()
+            .skip(1)
+            .filter(|props| is_volume(&basename(&props["name"])))
+            .map(|props| as_volume(props, &mounts))
+            .collect()
+    }
+
+    pub(crate) fn survey(&self) -> Result<Vec<Entry>, Error> {
+        let datasets = self.backend.list(
+            &self.root.to_string_lossy(),
+            Kind::Filesystem,
+            Some(2),
+            &[
+                "name",
+                "origin",
+                "mountpoint",
+                PROP_INCOMPLETE,
+                PROP_PER_MOUNT_CLONE,
+                PROP_EPHEMERAL,
+                PROP_MOUNT_COUNT,
+            ],
+        )?;
+
+        // Private clones are the children of a volume cloned from it
+        let mut clones = HashMap::<String, Vec<String>>::new();
+        for props in &datasets {
+            let name = Path::new(&props["name"]);
+            let parent = match name.parent() {
+                Some(parent) if parent.parent() == Some(&self.root) => parent,
+                _ => continue,
+            };
+            if Path::new(props["origin"].split('@').next().unwrap_or_default()) == parent {
+                clones
+                    .entry(basename(&parent.to_string_lossy()))
+                    .or_default()
+                    .push(basename(&props["name"]));
             }
         }
-        Ok(dss)
+
+        Ok(datasets
+            .iter()
+            .filter(|props| Path::new(&props["name"]).parent() == Some(&self.root))
+            .map(|props| {
+                let vol = basename(&props["name"]);
+                Entry {
+                    mountpoint: as_pathbuf(&props["mountpoint"]),
+                    incomplete: props[PROP_INCOMPLETE] == "on",
+                    per_mount_clone: props[PROP_PER_MOUNT_CLONE] == "on",
+                    ephemeral: props[PROP_EPHEMERAL] == "on",
+                    ever_mounted: props[PROP_MOUNT_COUNT].parse().unwrap_or(0) > 0,
+                    clones: clones.remove(&vol).unwrap_or_default(),
+                    vol,
+                }
+            })
+            .collect())
     }
 }
 
@@ -563,14 +1525,133 @@ pub struct Dataset {
     avail: u64,
 }
 
-fn parse_dataset(bs: &[u8]) -> Result<Dataset, csv::Error> {
-    csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_reader(bs)
-        .deserialize()
-        .next()
-        .unwrap()
+const DATASET_PROPS: &[&str] = &["name", "mountpoint", "creation", "used", "avail"];
+
+// `zfs list -p` output is shaped like a CSV record, so we can use `csv`'s
+// serde support for parsing the values.
+fn parse_dataset(props: &Properties) -> Result<Dataset, Error> {
+    let headers = csv::StringRecord::from(DATASET_PROPS.to_vec());
+    let record = DATASET_PROPS
+        .iter()
+        .map(|p| props.get(*p).map(String::as_str).unwrap_or(""))
+        .collect::<csv::StringRecord>();
+    let mut ds: Dataset = record.deserialize(Some(&headers))?;
+    ds.name = basename(&ds.name);
+    Ok(ds)
+}
+
+/// Properties reported in `Volume.Status`, on top of `DATASET_PROPS`.
+const STATUS_PROPS: &[&str] = &[
+    "refquota",
+    "compressratio",
+    "logicalused",
+    "written",
+    "origin",
+    "compression",
+    "atime",
+    "exec",
+    "setuid",
+    PROP_TTL,
+    PROP_LAST_MOUNTED,
+    PROP_LAST_UNMOUNTED,
+    PROP_MOUNT_COUNT,
+    PROP_LAST_CONTAINER,
+    PROP_PER_MOUNT_CLONE,
+    PROP_INHERITED_OPTIONS,
+    PROP_SOURCE_VOLUME,
+    PROP_SOURCE_SNAPSHOT,
+    PROP_REASON,
+    PROP_AUTO_SNAPSHOT,
+    PROP_SNAPSHOT_ON_UNMOUNT,
+    PROP_EPHEMERAL,
+    PROP_EXCLUSIVE,
+    PROP_TUNABLES,
+    PROP_PROFILE,
+];
+
+fn volume_props() -> Vec<&'static str> {
+    DATASET_PROPS
+        .iter()
+        .chain(STATUS_PROPS)
+        .chain(TUNABLES)
+        .cloned()
+        .collect()
+}
+
+fn as_volume(props: &Properties, mounts: &Table) -> Result<Volume, Error> {
+    fn bytes(value: &str) -> String {
+        value
+            .parse::<u128>()
+            .map(|n| Byte::from_bytes(n).get_appropriate_unit(true).to_string())
+            .unwrap_or_else(|_| value.to_string())
+    }
+
+    let prop = |name: &str| props.get(name).map(String::as_str).unwrap_or("-");
+    let ds = parse_dataset(props)?;
+
+    let mut status = HashMap::new();
+    status.insert("used".to_string(), bytes(prop("used")));
+    status.insert("available".to_string(), bytes(prop("avail")));
+    status.insert(
+        "refquota".to_string(),
+        match prop("refquota") {
+            "0" => "none".to_string(),
+            x => bytes(x),
+        },
+    );
+    status.insert("compressratio".to_string(), format!("{}x", prop("compressratio")));
+    status.insert("logicalused".to_string(), bytes(prop("logicalused")));
+    status.insert("written".to_string(), bytes(prop("written")));
+    if prop("origin") != "-" {
+        status.insert("origin".to_string(), prop("origin").to_string());
+    }
+    // The mount table is keyed by the names Docker uses, which may not be
+    // sanitized yet.
+    status.insert(
+        "mounted_by".to_string(),
+        mounts
+            .iter()
+            .filter(|(vol, _)| sanitize_vol(vol) == ds.name)
+            .flat_map(|(_, by)| by.iter())
+            .sorted()
+            .join(","),
+    );
+    for (key, name) in &[
+        ("last_mounted", PROP_LAST_MOUNTED),
+        ("last_unmounted", PROP_LAST_UNMOUNTED),
+    ] {
+        if let Some(t) = as_timestamp(prop(name)) {
+            status.insert((*key).to_string(), t.to_rfc3339());
+        }
+    }
+    status.insert(
+        "mount_count".to_string(),
+        match prop(PROP_MOUNT_COUNT) {
+            "-" => "0",
+            x => x,
+        }
+        .to_string(),
+    );
+    for (key, name) in &[
+        ("last_container", PROP_LAST_CONTAINER),
+        ("source_volume", PROP_SOURCE_VOLUME),
+        ("source_snapshot", PROP_SOURCE_SNAPSHOT),
+        ("reason", PROP_REASON),
+        ("profile", PROP_PROFILE),
+    ] {
+        if prop(name) != "-" {
+            status.insert((*key).to_string(), prop(name).to_string());
+        }
+    }
+    let opts = VolumeOptions::from_properties(props);
+    if !opts.inherited.is_empty() {
+        status.insert("inherited_options".to_string(), opts.inherited.join(","));
+    }
+    status.insert("options".to_string(), opts.to_string());
+
+    let mut vol = Volume::from(ds);
+    vol.status = Some(status);
+    Ok(vol)
 }
 
 impl From<Dataset> for Volume {
@@ -587,14 +1668,7 @@ impl From<Dataset> for Volume {
 impl VolumePlugin for Zfs {
     fn create(&self, rq: CreateRequest) -> Result<(), ErrorResponse> {
         info!("Volume.Create: {:?}", rq);
-        {
-            if self.exists(&rq.name)? {
-                Ok(())
-            } else {
-                self.do_create(&rq.name, rq.options.unwrap_or_default())
-            }
-        }
-        .map_err(|e| {
+        self.do_create(&rq.name, rq.options.unwrap_or_default()).map_err(|e| {
             error!("Volume.Create: {:?}", e);
             e.into()
         })
@@ -622,7 +1696,7 @@ impl VolumePlugin for Zfs {
 
     fn path(&self, rq: PathRequest) -> Result<PathResponse, ErrorResponse> {
         info!("Volume.Path: {:?}", rq);
-        self.get_mountpoint(&rq.name)
+        self.get_path(&rq.name)
             .map_err(|e| {
                 error!("Volume.Path: {:?}", e);
                 e.into()
@@ -647,7 +1721,7 @@ impl VolumePlugin for Zfs {
                 error!("Volume.Get: {:?}", e);
                 e.into()
             })
-            .map(|ds| GetResponse { volume: ds.into() })
+            .map(|volume| GetResponse { volume })
     }
 
     fn list(&self) -> Result<ListResponse, ErrorResponse> {
@@ -657,9 +1731,7 @@ impl VolumePlugin for Zfs {
                 error!("Volume.List: {:?}", e);
                 e.into()
             })
-            .map(|dss| ListResponse {
-                volumes: dss.into_iter().map(|ds| ds.into()).collect(),
-            })
+            .map(|volumes| ListResponse { volumes })
     }
 
     fn capabilities(&self) -> CapabilitiesResponse {
@@ -683,10 +1755,6 @@ fn ignore_mount_error(e: Error) -> Result<(), Error> {
     )
 }
 
-fn ignore_already_exists(e: Error) -> Result<(), Error> {
-    ignore_stderr_msg(e, "dataset already exists")
-}
-
 fn ignore_does_not_exist(e: Error) -> Result<(), Error> {
     ignore_stderr_msg(e, "dataset does not exist")
 }
@@ -701,44 +1769,773 @@ fn ignore_stderr_msg(e: Error, msg: &str) -> Result<(), Error> {
     }
 }
 
-fn as_pathbuf(stdout: Vec<u8>) -> Option<PathBuf> {
-    let s = String::from_utf8(stdout).expect("stdout not utf8");
-    let l = s.lines().nth(0);
-    match l {
-        None | Some("none") | Some("") => None,
+fn as_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    value.parse().ok().map(|secs| Utc.timestamp(secs, 0))
+}
+
+/// When the volume described by `props` was last used, ie. the latest of its
+/// creation, last mount and last unmount.
+pub(crate) fn last_used(props: &Properties) -> Option<DateTime<Utc>> {
+    ["creation", PROP_LAST_MOUNTED, PROP_LAST_UNMOUNTED]
+        .iter()
+        .filter_map(|prop| props.get(*prop).and_then(|x| as_timestamp(x)))
+        .max()
+}
+
+fn as_pathbuf(value: &str) -> Option<PathBuf> {
+    match value.lines().nth(0) {
+        None | Some("none") | Some("-") | Some("") => None,
         Some(x) => Some(PathBuf::from(x)),
     }
 }
 
-fn sanitize_vol(vol: &str) -> String {
+pub(crate) fn basename(dataset: &str) -> String {
+    Path::new(dataset)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| dataset.to_string())
+}
+
+/// Whether the dataset named `name` (below the root) backs a volume, rather
+/// than being some other dataset we keep around.
+pub(crate) fn is_volume(name: &str) -> bool {
+    sanitize_vol(name) == name
+}
+
+pub(crate) fn sanitize_vol(vol: &str) -> String {
     lazy_static! {
         static ref RE: Regex = Regex::new("[^-_a-zA-Z0-9]").unwrap();
     }
     RE.replace_all(vol, "_").to_string()
 }
 
-trait CommandExt {
-    fn run(&mut self) -> Result<Vec<u8>, Error>;
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl CommandExt for Command {
-    fn run(&mut self) -> Result<Vec<u8>, Error> {
-        match self.output() {
-            Err(e) => Err(Error::CmdIoError(format!("{:?}", self), e)),
-            Ok(out) => {
-                if out.status.success() {
-                    Ok(out.stdout)
-                } else {
-                    Err(Error::CmdError(format!("{:?}", self), out.stderr))
-                }
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    use crate::reconcile::{Discrepancy, Outcome};
+    use crate::testing::{props, Fixture};
+
+    fn create(zfs: &Zfs, name: &str, opts: &[(&str, &str)]) -> Result<(), ErrorResponse> {
+        zfs.create(CreateRequest {
+            name: name.to_string(),
+            options: Some(props(opts)),
+        })
+    }
+
+    fn mount(zfs: &Zfs, name: &str, id: &str) -> Result<PathBuf, ErrorResponse> {
+        zfs.mount(MountRequest {
+            name: name.to_string(),
+            id: id.to_string(),
+        })
+        .map(|rsp| PathBuf::from(rsp.mountpoint))
+    }
+
+    fn unmount(zfs: &Zfs, name: &str, id: &str) -> Result<(), ErrorResponse> {
+        zfs.unmount(UnmountRequest {
+            name: name.to_string(),
+            id: id.to_string(),
+        })
+    }
+
+    fn remove(zfs: &Zfs, name: &str) -> Result<(), ErrorResponse> {
+        zfs.remove(RemoveRequest {
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let fx = Fixture::new();
+
+        create(&fx, "cache:1", &[("refquota", "1GiB"), ("exec", "on")]).unwrap();
+        // Creating again is a no-op
+        create(&fx, "cache:1", &[]).unwrap();
+        let props = fx
+            .mem
+            .get("tank/zocker/cache_1", &["refquota", "exec", "setuid", "mountpoint"])
+            .unwrap();
+        assert_eq!(props["refquota"], "1073741824");
+        assert_eq!(props["exec"], "on");
+        assert_eq!(props["setuid"], "off");
+        assert_eq!(props["mountpoint"], "none");
+
+        let mountpoint = mount(&fx, "cache:1", "c1").unwrap();
+        assert_eq!(mountpoint, fx.dir.join("zocker/cache_1"));
+        assert!(mountpoint.is_dir());
+        assert_eq!(mount(&fx, "cache:1", "c2").unwrap(), mountpoint);
+
+        assert_eq!(
+            unmount(&fx, "cache:1", "c1").unwrap_err().err,
+            "Volume cache:1 is in use by: c2"
+        );
+        assert!(mountpoint.is_dir());
+        assert!(remove(&fx, "cache:1").is_err());
+
+        unmount(&fx, "cache:1", "c2").unwrap();
+        assert!(!mountpoint.exists());
+        assert_eq!(
+            fx.mem.get("tank/zocker/cache_1", &["mounted"]).unwrap()["mounted"],
+            "no"
+        );
+
+        assert_eq!(fx.list().unwrap().volumes.len(), 1);
+        remove(&fx, "cache:1").unwrap();
+        assert!(fx.list().unwrap().volumes.is_empty());
+    }
+
+    #[test]
+    fn test_mount_undone_unless_recorded() {
+        let fx = Fixture::new();
+        let table = fx.dir.join("mounts.json");
+        let mounts = Mounts::load(table.clone()).unwrap();
+        let zfs = Zfs::new(PathBuf::from("tank/zocker"), mounts, fx.mem.clone());
+        create(&zfs, "cache", &[]).unwrap();
+
+        // The mount table can't be written where a directory is in the way
+        fs::create_dir(table.with_extension("tmp")).unwrap();
+        assert!(mount(&zfs, "cache", "c1").is_err());
+        assert_eq!(
+            fx.mem.get("tank/zocker/cache", &["mounted"]).unwrap()["mounted"],
+            "no"
+        );
+        assert!(zfs.mounts.all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_sanitized_name() {
+        let fx = Fixture::new();
+        create(&fx, "cache:a", &[]).unwrap();
+        mount(&fx, "cache:a", "c1").unwrap();
+
+        // The volume may also be referred to by its dataset name
+        assert_eq!(
+            remove(&fx, "cache_a").unwrap_err().err,
+            "Volume cache_a is in use by: c1"
+        );
+        assert!(fx.exists("cache:a").unwrap());
+        assert_eq!(fx.owners_of("cache_a").unwrap(), vec!["c1".to_string()]);
+
+        unmount(&fx, "cache:a", "c1").unwrap();
+        remove(&fx, "cache_a").unwrap();
+        assert!(!fx.exists("cache:a").unwrap());
+        assert!(fx.mounts.all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_create_from() {
+        let fx = Fixture::new();
+
+        create(&fx, "master", &[]).unwrap();
+        fx.mem.write("tank/zocker/master", 4096).unwrap();
+        create(&fx, "branch", &[("from", "master")]).unwrap();
+
+        let props = fx
+            .mem
+            .get("tank/zocker/branch", &["origin", "referenced"])
+            .unwrap();
+        assert!(props["origin"].starts_with("tank/zocker/master@"));
+        assert_eq!(props["referenced"], "4096");
+
+        // The snapshot goes away along with the clone
+        remove(&fx, "branch").unwrap();
+        assert!(fx
+            .mem
+            .list("tank/zocker/master", Kind::Snapshot, None, &["name"])
+            .unwrap()
+            .is_empty());
+        remove(&fx, "master").unwrap();
+    }
+
+    #[test]
+    fn test_create_from_snapshot() {
+        let fx = Fixture::new();
+
+        create(&fx, "master", &[]).unwrap();
+        fx.mem.write("tank/zocker/master", 4096).unwrap();
+        fx.mem.snapshot("tank/zocker/master@nightly").unwrap();
+        fx.mem.write("tank/zocker/master", 1024).unwrap();
+
+        create(&fx, "branch", &[("from", "master@nightly")]).unwrap();
+        let branch = fx
+            .mem
+            .get(
+                "tank/zocker/branch",
+                &["origin", "referenced", PROP_SOURCE_VOLUME, PROP_SOURCE_SNAPSHOT],
+            )
+            .unwrap();
+        assert_eq!(branch["origin"], "tank/zocker/master@nightly");
+        assert_eq!(branch["referenced"], "4096");
+        assert_eq!(branch[PROP_SOURCE_VOLUME], "master");
+        assert_eq!(branch[PROP_SOURCE_SNAPSHOT], "nightly");
+
+        // The snapshot isn't ours to destroy
+        remove(&fx, "branch").unwrap();
+        assert!(fx.mem.get("tank/zocker/master@nightly", &["name"]).is_ok());
+
+        match fx.do_create("branch", props(&[("from", "missing")])) {
+            Err(Error::NoSuchVolumeError(vol)) => assert_eq!(vol, "missing"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match fx.do_create("branch", props(&[("from", "master@weekly")])) {
+            Err(Error::NoSuchSnapshotError(vol, snap)) => {
+                assert_eq!((vol.as_str(), snap.as_str()), ("master", "weekly"))
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert!(!fx.exists("branch").unwrap());
+    }
+
+    #[test]
+    fn test_remove_with_clones() {
+        let fx = Fixture::new();
+        create(&fx, "master", &[]).unwrap();
+        create(&fx, "branch", &[("from", "master")]).unwrap();
+        create(&fx, "other", &[("from", "master")]).unwrap();
+
+        match fx.do_remove("master") {
+            Err(Error::HasClonesError(vol, clones)) => {
+                assert_eq!(vol, "master");
+                assert_eq!(clones, vec!["branch", "other"]);
+            },
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // The newest clone takes over the snapshots
+        let zfs = fx.zfs.clone().with_clones(WithClones::Promote);
+        remove(&zfs, "master").unwrap();
+        assert!(!fx.exists("master").unwrap());
+        let origin = |ds: &str| fx.mem.get(ds, &["origin"]).unwrap()["origin"].clone();
+        assert_eq!(origin("tank/zocker/other"), "-");
+        assert!(origin("tank/zocker/branch").starts_with("tank/zocker/other@"));
+
+        // The volume is gone as far as Docker is concerned, and its name can
+        // be reused right away
+        let zfs = fx.zfs.clone().with_clones(WithClones::Defer);
+        remove(&zfs, "other").unwrap();
+        let names = fx.list().unwrap().volumes.into_iter().map(|vol| vol.name);
+        assert_eq!(names.collect::<Vec<_>>(), vec!["branch"]);
+        create(&fx, "other", &[]).unwrap();
+
+        // ..and it's destroyed along with its last clone
+        remove(&fx, "branch").unwrap();
+        let datasets = fx
+            .mem
+            .list("tank/zocker", Kind::Filesystem, None, &["name"])
+            .unwrap()
+            .into_iter()
+            .map(|props| props["name"].clone());
+        assert_eq!(
+            datasets.collect::<Vec<_>>(),
+            vec!["tank/zocker", "tank/zocker/other"]
+        );
+    }
+
+    #[test]
+    fn test_create_rolls_back() {
+        let fx = Fixture::new();
+
+        // Make `mkdir` fail
+        fs::create_dir(fx.dir.join("zocker/vol")).unwrap();
+        match fx.do_create("vol", HashMap::new()) {
+            Err(Error::TransactionError(_, "mkdir", _, _)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert!(!fx.exists("vol").unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_lifecycle() {
+        let fx = Fixture::new();
+        create(&fx, "master", &[]).unwrap();
+
+        let workers = (0..8)
+            .map(|i| {
+                let zfs = fx.zfs.clone();
+                thread::spawn(move || {
+                    let id = format!("c{}", i);
+                    create(&zfs, "branch", &[("from", "master")])?;
+                    mount(&zfs, "branch", &id)?;
+                    unmount(&zfs, "branch", &id)
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            // Unless somebody else still holds the volume
+            if let Err(e) = worker.join().unwrap() {
+                assert!(e.err.starts_with("Volume branch is in use by: "), e.err);
             }
         }
+
+        assert!(fx.mounts.owners("branch").unwrap().is_empty());
+        remove(&fx, "branch").unwrap();
+        remove(&fx, "master").unwrap();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_lock_timeout() {
+        let fx = Fixture::new();
+        let zfs = fx
+            .zfs
+            .clone()
+            .lock_timeout(Some(time::Duration::from_millis(10)));
+
+        let _lock = zfs.lock(&["vol:1"]).unwrap();
+        match zfs.do_create("vol:1", HashMap::new()) {
+            Err(Error::LockTimeoutError(vols, _)) => assert_eq!(vols, "vol_1"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        // Cloning locks the origin, too
+        match zfs.do_create("other", props(&[("from", "vol:1")])) {
+            Err(Error::LockTimeoutError(vols, _)) => assert_eq!(vols, "other, vol_1"),
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_per_mount_clone() {
+        let fx = Fixture::new();
+        create(&fx, "master", &[]).unwrap();
+        fx.mem.write("tank/zocker/master", 1024).unwrap();
+        create(&fx, "cache", &[("from", "master"), ("per-mount-clone", "on")]).unwrap();
+
+        let mp1 = mount(&fx, "cache", "c1").unwrap();
+        let mp2 = mount(&fx, "cache", "c2").unwrap();
+        assert_eq!(mp1, fx.dir.join("zocker/cache/c1"));
+        assert_eq!(mp2, fx.dir.join("zocker/cache/c2"));
+        assert!(mp1.is_dir() && mp2.is_dir());
+        // Mounting again returns the same clone
+        assert_eq!(mount(&fx, "cache", "c1").unwrap(), mp1);
+
+        // Clones are private, and start out from the volume's contents
+        fx.mem.write("tank/zocker/cache/c1", 2048).unwrap();
+        let referenced = |ds: &str| fx.mem.get(ds, &["referenced"]).unwrap()["referenced"].clone();
+        assert_eq!(referenced("tank/zocker/cache/c1"), "3072");
+        assert_eq!(referenced("tank/zocker/cache/c2"), "1024");
+        assert_eq!(referenced("tank/zocker/cache"), "1024");
+
+        // Clones aren't volumes, and aren't discrepancies either
+        let names = fx.list().unwrap().volumes.into_iter().map(|vol| vol.name).sorted();
+        assert_eq!(names.collect::<Vec<_>>(), vec!["cache", "master"]);
+        assert!(fx.reconcile(true).unwrap().is_empty());
+
+        unmount(&fx, "cache", "c1").unwrap();
+        assert!(!mp1.exists());
+        assert!(fx.mem.get("tank/zocker/cache/c1", &["name"]).is_err());
+        assert_eq!(
+            fx.path(PathRequest {
+                name: "cache".to_string()
+            })
+            .unwrap()
+            .mountpoint,
+            mp2.to_string_lossy()
+        );
+        assert!(remove(&fx, "cache").is_err());
+
+        unmount(&fx, "cache", "c2").unwrap();
+        assert!(!fx.dir.join("zocker/cache").exists());
+        remove(&fx, "cache").unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_on_unmount() {
+        let fx = Fixture::new();
+        create(&fx, "vol", &[("snapshot-on-unmount", "2")]).unwrap();
+        let snapshots = || {
+            fx.mem
+                .list("tank/zocker/vol", Kind::Snapshot, None, &["name", "defer_destroy"])
+                .unwrap()
+                .into_iter()
+                .filter(|props| props["defer_destroy"] != "on")
+                .map(|props| basename(&props["name"]))
+                .collect::<Vec<_>>()
+        };
+
+        for (i, id) in ["c1", "c2", "c3"].iter().enumerate() {
+            mount(&fx, "vol", id).unwrap();
+            fx.mem.write("tank/zocker/vol", (i as u64 + 1) * 1024).unwrap();
+            unmount(&fx, "vol", id).unwrap();
+        }
+        let taken = snapshots();
+        assert_eq!(taken.len(), 2);
+        assert!(taken[0].starts_with("vol@unmount-") && taken[0].ends_with("-c2"));
+        assert!(taken[1].ends_with("-c3"));
+
+        // The state a container left behind can be cloned for a closer look
+        let snapshot = taken[1].splitn(2, '@').nth(1).unwrap();
+        let from = format!("vol@{}", snapshot);
+        create(&fx, "debug", &[("from", &from)]).unwrap();
+        assert_eq!(
+            fx.mem.get("tank/zocker/debug", &["referenced"]).unwrap()["referenced"],
+            "6144"
+        );
+
+        // Unmounting on behalf of somebody who didn't mount takes none
+        mount(&fx, "vol", "c4").unwrap();
+        assert!(unmount(&fx, "vol", "c5").is_err());
+        assert_eq!(snapshots(), taken);
+
+        match fx.do_create(
+            "private",
+            props(&[("snapshot-on-unmount", "1"), ("per-mount-clone", "on")]),
+        ) {
+            Err(Error::VolumeOptionsError(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert!(create(&fx, "zero", &[("snapshot-on-unmount", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_reconcile_orphaned_clones() {
+        let fx = Fixture::new();
+        create(&fx, "cache", &[("per-mount-clone", "on")]).unwrap();
+        mount(&fx, "cache", "c1").unwrap();
+        mount(&fx, "cache", "c2").unwrap();
+
+        // The daemon was down when c1 went away
+        fx.mounts.remove("cache", "c1").unwrap();
+        let findings = fx.reconcile(false).unwrap();
+        assert_eq!(
+            findings
+                .iter()
+                .map(|f| (&f.discrepancy, &f.outcome))
+                .collect::<Vec<_>>(),
+            vec![(
+                &Discrepancy::OrphanedClone {
+                    vol: "cache".to_string(),
+                    id: "c1".to_string()
+                },
+                &Outcome::Repaired
+            )]
+        );
+        assert!(fx.mem.get("tank/zocker/cache/c1", &["name"]).is_err());
+        assert!(!fx.dir.join("zocker/cache/c1").exists());
+        assert!(fx.mem.get("tank/zocker/cache/c2", &["name"]).is_ok());
+
+        unmount(&fx, "cache", "c2").unwrap();
+        remove(&fx, "cache").unwrap();
+    }
+
+    #[test]
+    fn test_ephemeral() {
+        let fx = Fixture::new();
+        create(&fx, "master", &[]).unwrap();
+        create(&fx, "anon", &[("from", "master"), ("ephemeral", "on")]).unwrap();
+
+        mount(&fx, "anon", "c1").unwrap();
+        mount(&fx, "anon", "c2").unwrap();
+        assert!(unmount(&fx, "anon", "c1").is_err());
+        assert!(fx.exists("anon").unwrap());
+
+        // Gone with the last owner, origin snapshot and all
+        unmount(&fx, "anon", "c2").unwrap();
+        assert!(!fx.exists("anon").unwrap());
+        assert!(!fx.dir.join("zocker/anon").exists());
+        // As Docker does with `--rm`
+        remove(&fx, "anon").unwrap();
+        assert!(fx
+            .mem
+            .list("tank/zocker/master", Kind::Snapshot, None, &["name"])
+            .unwrap()
+            .is_empty());
+
+        // Left behind by a crash
+        create(&fx, "anon", &[("from", "master"), ("ephemeral", "on")]).unwrap();
+        create(&fx, "fresh", &[("ephemeral", "on")]).unwrap();
+        mount(&fx, "anon", "c1").unwrap();
+        fx.mounts.forget("anon").unwrap();
+        let findings = fx.reconcile(false).unwrap();
+        assert_eq!(
+            findings
+                .iter()
+                .map(|f| (&f.discrepancy, &f.outcome))
+                .collect::<Vec<_>>(),
+            vec![(
+                &Discrepancy::OrphanedEphemeral {
+                    vol: "anon".to_string()
+                },
+                &Outcome::Repaired
+            )]
+        );
+        assert!(!fx.exists("anon").unwrap());
+        assert!(fx.exists("fresh").unwrap());
+    }
+
+    #[test]
+    fn test_exclusive() {
+        let fx = Fixture::new();
+        create(&fx, "cache", &[("exclusive", "on")]).unwrap();
+        assert!(create(&fx, "private", &[("exclusive", "on"), ("per-mount-clone", "on")]).is_err());
+
+        mount(&fx, "cache", "c1").unwrap();
+        // Mounting again on behalf of the holder is fine
+        mount(&fx, "cache", "c1").unwrap();
+        assert_eq!(
+            mount(&fx, "cache", "c2").unwrap_err().err,
+            "Volume cache is mounted exclusively by: c1"
+        );
+
+        // Waiting gets nowhere while the holder keeps it mounted
+        let zfs = fx.zfs.clone().exclusive_wait(Some(time::Duration::from_millis(300)));
+        assert_eq!(
+            mount(&zfs, "cache", "c2").unwrap_err().err,
+            "Volume cache is mounted exclusively by: c1"
+        );
+
+        // A waiter gets it once the holder unmounts, whether or not it was
+        // already waiting by then
+        let zfs = fx.zfs.clone().exclusive_wait(Some(time::Duration::from_secs(60)));
+        let (started, waiting) = channel();
+        let waiter = thread::spawn(move || {
+            started.send(()).unwrap();
+            mount(&zfs, "cache", "c2")
+        });
+        waiting.recv().unwrap();
+        unmount(&fx, "cache", "c1").unwrap();
+        waiter.join().unwrap().unwrap();
+        assert_eq!(fx.owners_of("cache").unwrap(), vec!["c2".to_string()]);
+    }
+
+    #[test]
+    fn test_tunables() {
+        let fx = Fixture::new();
+        for (opt, value) in &[
+            ("compression", "zstd-20"),
+            ("compression", "yes"),
+            ("recordsize", "3000"),
+            ("recordsize", "32MiB"),
+            ("sync", "sometimes"),
+            ("copies", "4"),
+            ("quota", "lots"),
+        ] {
+            assert!(create(&fx, "db", &[(opt, value)]).is_err());
+        }
+        assert!(!fx.exists("db").unwrap());
+
+        create(
+            &fx,
+            "db",
+            &[
+                ("compression", "zstd-fast-20"),
+                ("recordsize", "16KiB"),
+                ("sync", "always"),
+                ("quota", "none"),
+            ],
+        )
+        .unwrap();
+        let got = fx
+            .mem
+            .get("tank/zocker/db", &["compression", "recordsize", "sync", "logbias"])
+            .unwrap();
+        assert_eq!(got["compression"], "zstd-fast-20");
+        assert_eq!(got["recordsize"], "16384");
+        assert_eq!(got["sync"], "always");
+        assert_eq!(got["logbias"], "latency");
+
+        // Tunables given to the origin are carried over to its clones
+        create(&fx, "branch", &[("from", "db"), ("sync", "standard")]).unwrap();
+        let got = fx
+            .mem
+            .get("tank/zocker/branch", &["compression", "recordsize", "sync"])
+            .unwrap();
+        assert_eq!(got["compression"], "zstd-fast-20");
+        assert_eq!(got["recordsize"], "16384");
+        assert_eq!(got["sync"], "standard");
+
+        let status = fx
+            .get(GetRequest {
+                name: "branch".to_string(),
+            })
+            .unwrap()
+            .volume
+            .status
+            .unwrap();
+        assert_eq!(
+            status["options"],
+            "from=db,refquota=262144000,compression=zstd-fast-20,atime=off,exec=off,setuid=off,\
+             recordsize=16384,sync=standard,quota=none,reason=clone"
+        );
+        assert_eq!(
+            status["inherited_options"],
+            "refquota,compression,atime,exec,setuid,recordsize,quota"
+        );
+    }
+
+    #[test]
+    fn test_policy_inherited_options() {
+        let fx = Fixture::new();
+        create(&fx, "img_cache", &[("exec", "on"), ("refquota", "1GiB")]).unwrap();
+        let policy = serde_json::from_str(
+            r#"{
+                "rules": [
+                    {
+                        "pattern": "^build",
+                        "allow": {
+                            "from": {},
+                            "exec": { "values": ["off"] },
+                            "setuid": {},
+                            "atime": {},
+                            "compression": {},
+                            "refquota": { "max": "1GiB" }
+                        }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let zfs = fx.zfs.clone().option_policy(policy);
+
+        assert_eq!(
+            create(&zfs, "build", &[("from", "img_cache")]).unwrap_err().err,
+            "Option exec=on is not allowed for volume build"
+        );
+        assert!(!fx.exists("build").unwrap());
+        create(&zfs, "build", &[("from", "img_cache"), ("exec", "off")]).unwrap();
+
+        // Defaults the origin was left with are not held against the clone
+        create(&fx, "plain", &[]).unwrap();
+        let policy = serde_json::from_str(
+            r#"{
+                "rules": [
+                    {
+                        "pattern": "^build",
+                        "allow": {
+                            "from": {},
+                            "refquota": { "max": "1GiB" }
+                        }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let zfs = fx.zfs.clone().option_policy(policy);
+        create(&zfs, "build2", &[("from", "plain")]).unwrap();
+        assert_eq!(
+            create(&zfs, "build3", &[("from", "img_cache")]).unwrap_err().err,
+            "Option exec=on is not allowed for volume build3"
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let fx = Fixture::new();
+        let profiles = vec![(
+            "build-cache".to_string(),
+            props(&[("exec", "on"), ("setuid", "on"), ("refquota", "15GiB")]),
+        )];
+        let policy = serde_json::from_str(
+            r#"{
+                "rules": [
+                    {
+                        "pattern": ".",
+                        "allow": {
+                            "profile": { "values": ["build-cache"] },
+                            "refquota": { "max": "1GiB" }
+                        }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let zfs = fx
+            .zfs
+            .clone()
+            .profiles(profiles.into_iter().collect())
+            .option_policy(policy);
+
+        assert_eq!(
+            create(&zfs, "cache", &[("profile", "nope")]).unwrap_err().err,
+            "Profile nope does not exist"
+        );
+        // Only what's given explicitly is checked against the policy
+        assert_eq!(
+            create(&zfs, "cache", &[("profile", "build-cache"), ("exec", "off")])
+                .unwrap_err()
+                .err,
+            "Option exec=off is not allowed for volume cache"
+        );
+
+        create(&zfs, "cache", &[("profile", "build-cache"), ("refquota", "1GiB")]).unwrap();
+        let got = fx
+            .mem
+            .get("tank/zocker/cache", &["exec", "setuid", "refquota"])
+            .unwrap();
+        assert_eq!(got["exec"], "on");
+        assert_eq!(got["setuid"], "on");
+        assert_eq!(got["refquota"], "1073741824");
+
+        let status = fx
+            .get(GetRequest {
+                name: "cache".to_string(),
+            })
+            .unwrap()
+            .volume
+            .status
+            .unwrap();
+        assert_eq!(status["profile"], "build-cache");
+        assert_eq!(
+            status["options"],
+            "profile=build-cache,refquota=1073741824,compression=on,atime=off,exec=on,setuid=on"
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let fx = Fixture::new();
+
+        create(&fx, "master", &[("refquota", "1GiB"), ("setuid", "on")]).unwrap();
+        fx.mem.write("tank/zocker/master", 2048).unwrap();
+        create(&fx, "branch", &[("from", "master"), ("exec", "on")]).unwrap();
+        mount(&fx, "branch", "c2").unwrap();
+        mount(&fx, "branch", "c1").unwrap();
+
+        let status = fx
+            .get(GetRequest {
+                name: "branch".to_string(),
+            })
+            .unwrap()
+            .volume
+            .status
+            .unwrap();
+        assert!(status.contains_key("used"));
+        assert!(status.contains_key("available"));
+        assert_eq!(status["logicalused"], "2.00 KiB");
+        assert_eq!(status["refquota"], "1024.00 MiB");
+        assert_eq!(status["compressratio"], "1.00x");
+        assert!(status["origin"].starts_with("tank/zocker/master@"));
+        assert_eq!(status["mounted_by"], "c1,c2");
+        assert_eq!(status["mount_count"], "2");
+        assert_eq!(status["last_container"], "c1");
+        assert!(status.contains_key("last_mounted"));
+        assert!(!status.contains_key("last_unmounted"));
+        // Options not given explicitly are taken over from the origin
+        assert_eq!(
+            status["options"],
+            "from=master,refquota=1073741824,compression=on,atime=off,exec=on,setuid=on,\
+             reason=clone"
+        );
+        assert_eq!(status["inherited_options"], "refquota,compression,atime,setuid");
+        assert_eq!(status["source_volume"], "master");
+        assert_eq!(
+            format!("tank/zocker/master@{}", status["source_snapshot"]),
+            status["origin"]
+        );
+        assert_eq!(status["reason"], "clone");
+
+        let volumes = fx.list().unwrap().volumes;
+        let master = volumes.iter().find(|vol| vol.name == "master").unwrap();
+        let status = master.status.as_ref().unwrap();
+        assert_eq!(status["refquota"], "1024.00 MiB");
+        assert_eq!(status["mounted_by"], "");
+        assert_eq!(status["mount_count"], "0");
+        assert!(!status.contains_key("origin"));
+        assert!(!status.contains_key("inherited_options"));
+        assert!(!status.contains_key("source_volume"));
+        assert!(!status.contains_key("reason"));
+    }
 
     #[test]
     fn serde_dataset() {